@@ -0,0 +1,97 @@
+use std::{any::Any, sync::Arc};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use magma_ecs::World;
+use parking_lot::RwLock;
+
+const ENTITIES: usize = 100_000;
+
+#[derive(Clone, Copy)]
+struct Transform([f32; 16]);
+
+#[derive(Clone, Copy)]
+struct Velocity([f32; 3]);
+
+/// The previous storage layout: one allocation, lock and downcast per component.
+type BoxedColumn = Vec<Option<Arc<RwLock<dyn Any + Send + Sync>>>>;
+
+fn boxed_column<T: Any + Send + Sync + Copy>(value: T) -> BoxedColumn {
+    (0..ENTITIES)
+        .map(|_| Some(Arc::new(RwLock::new(value)) as Arc<RwLock<dyn Any + Send + Sync>>))
+        .collect()
+}
+
+fn iterate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("iterate_100k");
+
+    let transforms = boxed_column(Transform([1.0; 16]));
+    let velocities = boxed_column(Velocity([1.0; 3]));
+    group.bench_function("boxed_components", |b| {
+        b.iter(|| {
+            transforms
+                .iter()
+                .zip(velocities.iter())
+                .for_each(|(transform, velocity)| {
+                    let mut transform = transform.as_ref().unwrap().write();
+                    let velocity = velocity.as_ref().unwrap().read();
+                    let transform = transform.downcast_mut::<Transform>().unwrap();
+                    let velocity = velocity.downcast_ref::<Velocity>().unwrap();
+                    transform.0[12] += velocity.0[0];
+                    transform.0[13] += velocity.0[1];
+                    transform.0[14] += velocity.0[2];
+                });
+            black_box(&transforms);
+        })
+    });
+
+    let world = World::new();
+    world.register_component::<Transform>();
+    world.register_component::<Velocity>();
+    world
+        .create_entity_batch((Transform([1.0; 16]), Velocity([1.0; 3])), ENTITIES)
+        .unwrap();
+
+    group.bench_function("typed_query", |b| {
+        b.iter(|| {
+            world
+                .query::<(&mut Transform, &Velocity)>()
+                .for_each(|(transform, velocity)| {
+                    transform.0[12] += velocity.0[0];
+                    transform.0[13] += velocity.0[1];
+                    transform.0[14] += velocity.0[2];
+                })
+                .unwrap();
+        })
+    });
+
+    group.bench_function("query_entity", |b| {
+        b.iter(|| {
+            world
+                .query()
+                .with_component::<Transform>()
+                .unwrap()
+                .with_component::<Velocity>()
+                .unwrap()
+                .run(|entities| {
+                    for entity in entities {
+                        entity
+                            .component_ref(|velocity: &Velocity| {
+                                entity
+                                    .component_mut(|transform: &mut Transform| {
+                                        transform.0[12] += velocity.0[0];
+                                        transform.0[13] += velocity.0[1];
+                                        transform.0[14] += velocity.0[2];
+                                    })
+                                    .unwrap()
+                            })
+                            .unwrap();
+                    }
+                });
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, iterate);
+criterion_main!(benches);
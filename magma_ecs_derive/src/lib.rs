@@ -0,0 +1,116 @@
+//! Derive macros for [`magma_ecs`](https://docs.rs/magma_ecs).
+//! Enable the `derive` feature of `magma_ecs` to use them.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, parse_quote, Data, DeriveInput, Error, Fields, WherePredicate};
+
+/// Implement `ComponentSet` for a struct with named fields, so it can be used to create entities.
+/// Every field is added as a component. Fields marked with `#[bundle]` are nested bundles, their components are added instead.
+/// ```
+/// use magma_ecs::{Bundle, World};
+///
+/// struct Health(u32);
+/// struct Speed(f32);
+/// struct Name(&'static str);
+///
+/// #[derive(Bundle)]
+/// struct Movement {
+///     speed: Speed,
+/// }
+///
+/// #[derive(Bundle)]
+/// struct Player {
+///     health: Health,
+///     name: Name,
+///     #[bundle]
+///     movement: Movement,
+/// }
+///
+/// let world = World::new();
+/// world.register_component::<Health>();
+/// world.register_component::<Speed>();
+/// world.register_component::<Name>();
+///
+/// let player = world
+///     .create_entity(Player {
+///         health: Health(100),
+///         name: Name("player"),
+///         movement: Movement { speed: Speed(1.5) },
+///     })
+///     .unwrap();
+/// assert_eq!(world.entity(player).unwrap().get::<Speed>().unwrap().0, 1.5);
+/// ```
+#[proc_macro_derive(Bundle, attributes(bundle))]
+pub fn derive_bundle(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    bundle(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn bundle(mut input: DeriveInput) -> Result<TokenStream2, Error> {
+    let ecs = quote!(::magma_ecs::entities::component_set);
+    let Data::Struct(data) = &input.data else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Bundle can only be derived for structs",
+        ));
+    };
+    let Fields::Named(fields) = &data.fields else {
+        return Err(Error::new_spanned(
+            &input.ident,
+            "Bundle can only be derived for structs with named fields",
+        ));
+    };
+
+    let mut type_ids = vec![];
+    let mut type_names = vec![];
+    let mut visits = vec![];
+    let mut bounds: Vec<WherePredicate> = vec![];
+    for field in &fields.named {
+        let name = &field.ident;
+        let ty = &field.ty;
+        if field
+            .attrs
+            .iter()
+            .any(|attr| attr.path().is_ident("bundle"))
+        {
+            type_ids.push(quote!(type_ids.extend(<#ty as #ecs::ComponentSet>::type_ids());));
+            type_names.push(quote!(type_names.extend(<#ty as #ecs::ComponentSet>::type_names());));
+            visits.push(quote!(#ecs::ComponentSet::for_components(self.#name, visitor);));
+            bounds.push(parse_quote!(#ty: #ecs::ComponentSet));
+        } else {
+            type_ids.push(quote!(type_ids.push(::std::any::TypeId::of::<#ty>());));
+            type_names.push(quote!(type_names.push(::std::any::type_name::<#ty>());));
+            visits.push(quote!(visitor.visit(self.#name);));
+            bounds.push(
+                parse_quote!(#ty: ::std::any::Any + ::std::marker::Send + ::std::marker::Sync),
+            );
+        }
+    }
+
+    input.generics.make_where_clause().predicates.extend(bounds);
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #ecs::ComponentSet for #ident #ty_generics #where_clause {
+            fn type_ids() -> ::std::vec::Vec<::std::any::TypeId> {
+                let mut type_ids = ::std::vec::Vec::new();
+                #(#type_ids)*
+                type_ids
+            }
+
+            fn type_names() -> ::std::vec::Vec<&'static str> {
+                let mut type_names = ::std::vec::Vec::new();
+                #(#type_names)*
+                type_names
+            }
+
+            fn for_components<V: #ecs::ComponentVisitor>(self, visitor: &mut V) {
+                #(#visits)*
+            }
+        }
+    })
+}
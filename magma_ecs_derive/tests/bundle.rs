@@ -0,0 +1,64 @@
+use std::any::{type_name, TypeId};
+
+use magma_ecs::{entities::component_set::ComponentSet, Bundle, World};
+
+#[derive(Debug, PartialEq)]
+struct Health(u32);
+#[derive(Debug, PartialEq)]
+struct Speed(f32);
+#[derive(Debug, PartialEq)]
+struct Tag<T>(T);
+
+#[derive(Bundle)]
+struct Movement {
+    speed: Speed,
+}
+
+#[derive(Bundle)]
+struct Player<T: Send + Sync + 'static> {
+    health: Health,
+    tag: Tag<T>,
+    #[bundle]
+    movement: Movement,
+}
+
+#[test]
+fn bundle_type_ids() {
+    assert_eq!(
+        Player::<u8>::type_ids(),
+        vec![
+            TypeId::of::<Health>(),
+            TypeId::of::<Tag<u8>>(),
+            TypeId::of::<Speed>()
+        ]
+    );
+    assert_eq!(
+        Player::<u8>::type_names(),
+        vec![
+            type_name::<Health>(),
+            type_name::<Tag<u8>>(),
+            type_name::<Speed>()
+        ]
+    );
+}
+
+#[test]
+fn create_entity_from_bundle() {
+    let world = World::new();
+    world.register_component::<Health>();
+    world.register_component::<Speed>();
+    world.register_component::<Tag<u8>>();
+
+    let player = world
+        .create_entity(Player {
+            health: Health(100),
+            tag: Tag(1_u8),
+            movement: Movement { speed: Speed(2.0) },
+        })
+        .unwrap();
+
+    let player = world.entity(player).unwrap();
+    assert_eq!(*player.get::<Health>().unwrap(), Health(100));
+    assert_eq!(*player.get::<Tag<u8>>().unwrap(), Tag(1));
+    assert_eq!(*player.get::<Speed>().unwrap(), Speed(2.0));
+}
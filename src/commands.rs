@@ -0,0 +1,177 @@
+use std::{any::Any, mem};
+
+use parking_lot::Mutex;
+
+use crate::{
+    entities::{component_set::ComponentSet, entity::Entity},
+    error::EntityError,
+    World,
+};
+
+/// A structural change recorded by [`Commands`].
+type Command = Box<dyn FnOnce(&World) -> Result<(), EntityError> + Send>;
+
+/// The commands of a [`World`] that are waiting to be applied.
+#[derive(Default)]
+pub(crate) struct CommandQueue(Mutex<Vec<Command>>);
+
+impl CommandQueue {
+    fn append(&self, commands: &mut Vec<Command>) {
+        self.0.lock().append(commands);
+    }
+
+    /// Apply all commands to the world and return the errors of the commands that failed.
+    /// Commands recorded while applying are applied as well.
+    pub(crate) fn apply(&self, world: &World) -> Vec<EntityError> {
+        let mut errors = vec![];
+        loop {
+            let commands = mem::take(&mut *self.0.lock());
+            if commands.is_empty() {
+                return errors;
+            }
+            errors.extend(
+                commands
+                    .into_iter()
+                    .filter_map(|command| command(world).err()),
+            );
+        }
+    }
+}
+
+impl std::fmt::Debug for CommandQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CommandQueue")
+            .field(&self.0.lock().len())
+            .finish()
+    }
+}
+
+/// Records structural changes to a [`World`], which are applied later instead of locking the entities right away.
+/// Get one with [`World::commands`].
+///
+/// The recorded commands are handed to the world when [`Commands`] is dropped.
+/// The [`Dispatcher`](crate::systems::dispatcher::Dispatcher) applies them after every stage,
+/// otherwise they are applied with [`World::apply_commands`].
+/// Commands are applied in the order they were recorded. Commands that fail, for example because the entity was despawned in the meantime, are skipped
+/// and their errors are returned by [`World::apply_commands`] or [`Dispatcher::dispatch`](crate::systems::dispatcher::Dispatcher::dispatch).
+pub struct Commands<'w> {
+    world: &'w World,
+    queue: Vec<Command>,
+}
+
+impl<'w> Commands<'w> {
+    pub(crate) fn new(world: &'w World) -> Self {
+        Self {
+            world,
+            queue: vec![],
+        }
+    }
+
+    /// Record a custom command.
+    pub fn add(&mut self, command: impl FnOnce(&World) + Send + 'static) -> &mut Self {
+        self.add_fallible(move |world| {
+            command(world);
+            Ok(())
+        })
+    }
+
+    /// Record a custom command that can fail.
+    pub fn add_fallible(
+        &mut self,
+        command: impl FnOnce(&World) -> Result<(), EntityError> + Send + 'static,
+    ) -> &mut Self {
+        self.queue.push(Box::new(command));
+        self
+    }
+
+    /// Spawn an entity with components. Fails if a component is not registered.
+    pub fn spawn<C: ComponentSet + Send + 'static>(&mut self, components: C) -> &mut Self {
+        self.add_fallible(move |world| world.create_entity(components).map(|_| ()))
+    }
+
+    /// Despawn an entity.
+    pub fn despawn(&mut self, entity: Entity) -> &mut Self {
+        self.add_fallible(move |world| world.entity_mut(entity)?.despawn())
+    }
+
+    /// Add a component to an entity. An already present component of the same type gets replaced.
+    pub fn insert<T: Any + Send + Sync>(&mut self, entity: Entity, component: T) -> &mut Self {
+        self.add_fallible(move |world| {
+            world.entity_mut(entity)?.insert(component)?;
+            Ok(())
+        })
+    }
+
+    /// Remove a component from an entity.
+    pub fn remove<T: Any + Send + Sync>(&mut self, entity: Entity) -> &mut Self {
+        self.add_fallible(move |world| {
+            world.entity_mut(entity)?.remove::<T>()?;
+            Ok(())
+        })
+    }
+
+    /// Add a resource to the world. An already present resource of the same type gets replaced.
+    pub fn insert_resource<T: Any + Send + Sync>(&mut self, resource: T) -> &mut Self {
+        self.add(move |world| world.resources.insert(resource))
+    }
+}
+
+impl Drop for Commands<'_> {
+    fn drop(&mut self) {
+        self.world.commands.append(&mut self.queue);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::EntityError, World};
+
+    #[test]
+    fn apply_commands() {
+        let world = World::new();
+        world.register_component::<u32>();
+        world.register_component::<f32>();
+        let entity = world.create_entity((10_u32,)).unwrap();
+
+        world
+            .commands()
+            .spawn((20_u32,))
+            .insert(entity, 1.5_f32)
+            .remove::<u32>(entity)
+            .insert_resource(5_u64)
+            .add(|world| {
+                world.commands().insert_resource(6_u64);
+            });
+        assert!(!world.entity(entity).unwrap().contains::<f32>());
+
+        assert_eq!(world.apply_commands(), vec![]);
+        let entity_ref = world.entity(entity).unwrap();
+        assert!(entity_ref.contains::<f32>());
+        assert!(!entity_ref.contains::<u32>());
+        world
+            .resource_ref(|resource: &u64| assert_eq!(*resource, 6))
+            .unwrap();
+
+        world.commands().despawn(entity).despawn(entity);
+        assert_eq!(world.apply_commands(), vec![EntityError::StaleEntity]);
+        assert!(world.entity(entity).is_err());
+        let mut count = 0;
+        world.query::<&u32>().for_each(|_| count += 1).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn failing_commands() {
+        let world = World::new();
+        world.register_component::<u32>();
+        world.commands().spawn((1_u32, 1_u64)).spawn((2_u32,));
+
+        assert_eq!(
+            world.apply_commands(),
+            vec![EntityError::ComponentNotRegistered("u64")]
+        );
+        let mut count = 0;
+        world.query::<&u32>().for_each(|_| count += 1).unwrap();
+        assert_eq!(count, 1);
+    }
+}
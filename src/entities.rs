@@ -1,214 +1,903 @@
+pub(crate) mod archetype;
+pub(crate) mod column;
+/// Provides [`ComponentId`](component::ComponentId) and [`ComponentDescriptor`](component::ComponentDescriptor) for components defined at runtime
+pub mod component;
 pub mod component_set;
+/// Provides the [`Entity`](entity::Entity) handle
+pub mod entity;
+/// Provides [`EntityRef`](entity_ref::EntityRef) and [`EntityMut`](entity_ref::EntityMut) for accessing a single entity
+pub mod entity_ref;
+/// Provides [`DeferredWorld`](hooks::DeferredWorld), which is passed to component hooks
+pub mod hooks;
 pub mod query;
-/// Output of running a [`Query`]
+/// Output of running a [`Query`](query::Query)
 pub mod query_entity;
+pub(crate) mod registry;
+/// Provides [`RemovedComponents`](removed::RemovedComponents) for reading removed components
+pub mod removed;
+pub(crate) mod sparse_set;
+pub(crate) mod tick;
 
-use component_set::ComponentSet;
-use parking_lot::RwLock;
+use archetype::{Archetype, Archetypes};
+use column::Column;
+use component::{ComponentDescriptor, ComponentId};
+use component_set::{ComponentSet, ComponentVisitor};
+use entity::Entity;
+use entity_ref::{ComponentMut, ComponentPtr, ComponentPtrMut, ComponentRef, EntityRef};
+use hooks::ComponentHooks;
+use parking_lot::{
+    MappedRwLockReadGuard, MappedRwLockWriteGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+use sparse_set::SparseSet;
 
 use std::{
-    any::{Any, TypeId},
-    collections::HashMap,
-    sync::Arc,
+    any::{type_name, Any, TypeId},
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
 };
 
-use query::Query;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use registry::{ComponentInfo, Registry};
+use removed::RemovedComponents;
 use roaring::RoaringBitmap;
+use tick::SystemTicks;
 
 use crate::error::EntityError;
 
-pub(crate) type Component = Arc<RwLock<dyn Any + Send + Sync>>;
-pub(crate) type ComponentMap = HashMap<TypeId, RwLock<Vec<Option<Component>>>>;
+/// How the components of a type are stored.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StorageType {
+    /// Store the component in the archetype tables. This is the fastest for iteration.
+    #[default]
+    Table,
+    /// Store the component in a sparse set. Adding and removing the component doesn't move the entity between archetypes,
+    /// which makes it a good fit for components that are added and removed frequently.
+    SparseSet,
+}
 
+/// Bookkeeping for one entity slot.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct EntityMeta {
+    pub(crate) generation: u32,
+    pub(crate) alive: bool,
+    /// The bits of all components of the entity, including sparse set components.
+    pub(crate) mask: RoaringBitmap,
+    /// The archetype the entity is stored in.
+    pub(crate) archetype: usize,
+    /// The row of the entity in its archetype.
+    pub(crate) row: usize,
+}
+
+/// Stores all entities and their components.
+///
+/// Entities with the same set of table components share an [`Archetype`] table.
+/// Adding or removing table components moves an entity to another archetype.
+/// Components registered with [`StorageType::SparseSet`] are stored outside of the tables.
+///
+/// Components can be registered through a shared reference, so systems can register new components at runtime.
+///
+/// Locks are always taken in this order: archetype rows, entity map, columns, sparse sets, removal logs.
+/// The component registry is only locked for looking up or adding a single component and never while taking another lock.
 #[derive(Debug, Default)]
 pub struct Entities {
-    components: ComponentMap,
-    bit_masks: HashMap<TypeId, u32>,
-    map: RwLock<Vec<RoaringBitmap>>,
+    registry: RwLock<Registry>,
+    map: RwLock<Vec<EntityMeta>>,
+    archetypes: RwLock<Archetypes>,
+    /// Increased every time a system runs. Used for change detection.
+    tick: AtomicU32,
+    /// The tick at which the removal logs were last cleared.
+    removed_cleared: AtomicU32,
+    /// Set once a hook is registered for any component, so structural changes can skip looking up hooks until then.
+    has_hooks: AtomicBool,
+}
+
+/// Where a component of an entity is stored.
+enum Location<'a> {
+    Table(&'a RwLock<Column>, usize),
+    SparseSet(&'a RwLock<SparseSet>),
+}
+
+/// Pushes the components of a [`ComponentSet`] to the columns of an archetype.
+struct PushComponents<'a> {
+    entities: &'a Entities,
+    archetype: &'a Archetype,
+    entity: Entity,
+    tick: u32,
+    /// The bits of the components in the order they are visited.
+    bits: &'a [u32],
+    visited: usize,
+    pushed: RoaringBitmap,
+}
+
+impl ComponentVisitor for PushComponents<'_> {
+    fn visit<T: Any + Send + Sync>(&mut self, component: T) {
+        let bit = self.bits[self.visited];
+        self.visited += 1;
+        if let Some(sparse_set) = self.entities.sparse_set(bit) {
+            sparse_set.write().insert(self.entity, component, self.tick);
+            return;
+        }
+        let mut column = self.archetype.columns[&bit].write();
+        if self.pushed.insert(bit) {
+            column.push(component, self.tick);
+        } else {
+            // the set contains the component type more than once, the last one wins
+            let row = column.len() - 1;
+            column.replace(row, component, self.tick);
+        }
+    }
 }
 
 impl Entities {
-    pub(crate) fn register_component<T: Any + Send + Sync>(&mut self) {
-        let type_id = TypeId::of::<T>();
-        self.components.insert(type_id, RwLock::new(vec![]));
-        self.bit_masks.insert(type_id, self.bit_masks.len() as u32);
+    /// Register a component with [`StorageType::Table`] and return its bit.
+    /// Returns the existing bit if the component is already registered, whatever its storage.
+    pub(crate) fn register_component<T: Any + Send + Sync>(&self) -> u32 {
+        if let Some(bit) = self.get_bitmask(&TypeId::of::<T>()) {
+            return bit;
+        }
+        self.registry.write().register::<T>(StorageType::Table)
     }
 
-    pub(crate) fn create_entity(&self, components: impl ComponentSet) -> Result<(), EntityError> {
-        let mut map = self.map.write();
-        let mut result = Ok(());
-        if let Some((index, _)) = map
-            .par_iter()
-            .enumerate()
-            .find_any(|(_, mask)| mask.is_empty())
-        {
-            components.for_components(|type_id, component| {
-                if let Some(component_vec) = self.components.get(&type_id) {
-                    let mut component_vec = component_vec.write();
-                    let component_stored = component_vec.get_mut(index).unwrap();
-                    *component_stored = Some(component);
-
-                    let bit_mask = self.bit_masks.get(&type_id).unwrap();
-                    map[index].insert(*bit_mask);
-                } else {
-                    result = Err(EntityError::ComponentNotRegistered);
-                };
-            });
-            result
+    /// Register a component and return its bit. Returns the existing bit if the component is already registered.
+    /// Panics if it was registered with a different storage, e.g. automatically by inserting it.
+    pub(crate) fn register_component_with_storage<T: Any + Send + Sync>(
+        &self,
+        storage: StorageType,
+    ) -> u32 {
+        let bit = match self.get_bitmask(&TypeId::of::<T>()) {
+            Some(bit) => bit,
+            None => self.registry.write().register::<T>(storage),
+        };
+        assert!(
+            self.sparse_set(bit).is_some() == (storage == StorageType::SparseSet),
+            "component {} is already registered with a different storage type",
+            type_name::<T>()
+        );
+        bit
+    }
+
+    /// Register a component defined at runtime. Returns the existing id if a component with the same name is already registered.
+    pub(crate) fn register_dynamic_component(
+        &self,
+        descriptor: ComponentDescriptor,
+    ) -> ComponentId {
+        if let Some(bit) = self.registry.read().existing_dynamic(&descriptor) {
+            return ComponentId(bit);
+        }
+        ComponentId(self.registry.write().register_dynamic(descriptor))
+    }
+
+    pub(crate) fn component_id<T: Any>(&self) -> Option<ComponentId> {
+        self.get_bitmask(&TypeId::of::<T>()).map(ComponentId)
+    }
+
+    pub(crate) fn component_id_by_name(&self, name: &str) -> Option<ComponentId> {
+        self.registry.read().dynamic_bit(name).map(ComponentId)
+    }
+
+    pub(crate) fn component_name(&self, id: ComponentId) -> Option<String> {
+        self.registry
+            .read()
+            .get(id.0)
+            .map(|component| component.name.to_string())
+    }
+
+    /// Change the hooks of a registered component.
+    pub(crate) fn set_hooks(&self, bit: u32, set: impl FnOnce(&mut ComponentHooks)) {
+        set(&mut self.component(bit).hooks.write());
+        self.has_hooks.store(true, Ordering::Release);
+    }
+
+    /// Check if a hook was registered for any component.
+    pub(crate) fn has_hooks(&self) -> bool {
+        self.has_hooks.load(Ordering::Acquire)
+    }
+
+    /// The hooks of a registered component.
+    pub(crate) fn hooks(&self, bit: u32) -> ComponentHooks {
+        self.component(bit).hooks.read().clone()
+    }
+
+    /// Get a registered component by its bit.
+    ///
+    /// The registry and the archetypes store their entries boxed and never remove them,
+    /// so an entry keeps its address when the vector grows and lives as long as `self`.
+    /// This makes returning references that outlive the lock sound, here and in the other component and archetype accessors.
+    fn component(&self, bit: u32) -> &ComponentInfo {
+        let component: *const ComponentInfo = self
+            .registry
+            .read()
+            .get(bit)
+            .expect("component is registered");
+        // SAFETY: the component has a stable address, see above.
+        unsafe { &*component }
+    }
+
+    /// The bit of a component id. Fails if the id doesn't belong to a registered component.
+    pub(crate) fn bit_of_id(&self, id: ComponentId) -> Result<u32, EntityError> {
+        self.registry
+            .read()
+            .get(id.0)
+            .map(|_| id.0)
+            .ok_or(EntityError::ComponentIdNotRegistered)
+    }
+
+    /// All registered components.
+    fn components(&self) -> Vec<&ComponentInfo> {
+        let registry = self.registry.read();
+        registry
+            .iter()
+            .map(|component| {
+                let component: *const ComponentInfo = component;
+                // SAFETY: the component has a stable address, see `Entities::component`.
+                unsafe { &*component }
+            })
+            .collect()
+    }
+
+    /// The bit of a registered component.
+    pub(crate) fn bit_of<T: Any>(&self) -> Result<u32, EntityError> {
+        self.get_bitmask(&TypeId::of::<T>())
+            .ok_or(EntityError::ComponentNotRegistered(type_name::<T>()))
+    }
+
+    /// The tick changes are marked with.
+    /// This is the tick of the system running on this thread or the tick after the last system run.
+    pub(crate) fn change_tick(&self) -> u32 {
+        SystemTicks::current().map_or_else(
+            || self.tick.load(Ordering::Acquire) + 1,
+            |ticks| ticks.this_run,
+        )
+    }
+
+    /// The ticks of the system running on this thread.
+    /// Outside of systems, every tick since the world was created is newer than `last_run`.
+    pub(crate) fn current_ticks(&self) -> SystemTicks {
+        SystemTicks::current().unwrap_or(SystemTicks {
+            last_run: 0,
+            this_run: self.change_tick(),
+        })
+    }
+
+    /// Get the ticks for a new system run.
+    pub(crate) fn system_ticks(&self, last_run: u32) -> SystemTicks {
+        SystemTicks {
+            last_run,
+            this_run: self.tick.fetch_add(1, Ordering::AcqRel) + 1,
+        }
+    }
+
+    /// Keep the values of removed `T` components in the removal log.
+    pub(crate) fn track_removed_values<T: Any + Send + Sync>(&self) -> Result<(), EntityError> {
+        let bit = self.bit_of::<T>()?;
+        self.component(bit).removed.write().keep_values = true;
+        Ok(())
+    }
+
+    /// Get the `T` components removed since the last run of the current system.
+    pub(crate) fn removed<T: Any + Send + Sync>(
+        &self,
+    ) -> Result<RemovedComponents<'_, T>, EntityError> {
+        let bit = self.bit_of::<T>()?;
+        Ok(RemovedComponents::new(
+            self.component(bit).removed.read(),
+            self.current_ticks(),
+        ))
+    }
+
+    /// Drop the removals that happened before the last call, so every system sees each removal once before it is dropped.
+    /// Advances the tick, so removals made outside of systems between two calls are told apart.
+    pub(crate) fn clear_removed(&self) {
+        let tick = self.tick.fetch_add(1, Ordering::AcqRel);
+        let ticks = SystemTicks {
+            last_run: self.removed_cleared.swap(tick, Ordering::AcqRel),
+            this_run: tick + 1,
+        };
+        for component in self.components() {
+            component.removed.write().clear_until(ticks);
+        }
+    }
+
+    /// Swap remove `row` from the column of the component with `bit` and record the removal.
+    fn remove_from_column(&self, bit: u32, column: &mut Column, row: usize, entity: Entity) {
+        let mut log = self.component(bit).removed.write();
+        let value = if log.keep_values {
+            Some(column.swap_remove_boxed(row))
         } else {
-            self.components
-                .par_iter()
-                .for_each(|(_, components)| components.write().push(None));
-            map.push(RoaringBitmap::new());
-
-            let index = map.len() - 1;
-            components.for_components(|type_id, component| {
-                if let Some(component_vec) = self.components.get(&type_id) {
-                    let mut component_vec = component_vec.write();
-                    let component_stored = component_vec.get_mut(index).unwrap();
-                    *component_stored = Some(component);
-
-                    let bit_mask = self.bit_masks.get(&type_id).unwrap();
-                    map[index].insert(*bit_mask);
-                } else {
-                    result = Err(EntityError::ComponentNotRegistered);
-                };
+            column.swap_remove(row);
+            None
+        };
+        log.push(entity, self.change_tick(), value);
+    }
+
+    /// Remove a sparse set component of an entity and record the removal.
+    fn remove_from_sparse_set(&self, bit: u32, entity: Entity) {
+        self.component(bit)
+            .sparse_set
+            .as_ref()
+            .expect("component is stored in a sparse set")
+            .write()
+            .remove_with(entity, |column, row| {
+                self.remove_from_column(bit, column, row, entity)
             });
-            result
+    }
+
+    /// Swap remove `row` from the column of the component with `bit`, record the removal and return the value.
+    fn take_from_column(
+        &self,
+        bit: u32,
+        column: &mut Column,
+        row: usize,
+        entity: Entity,
+    ) -> Box<dyn Any + Send + Sync> {
+        self.component(bit)
+            .removed
+            .write()
+            .push(entity, self.change_tick(), None);
+        column.swap_remove_boxed(row)
+    }
+
+    /// The bits of the components stored in archetype tables.
+    fn table_mask(&self, mask: &RoaringBitmap) -> RoaringBitmap {
+        mask - self.registry.read().sparse()
+    }
+
+    /// The bits of all components stored in sparse sets.
+    pub(crate) fn sparse_bits(&self) -> RoaringBitmap {
+        self.registry.read().sparse().clone()
+    }
+
+    pub(crate) fn sparse_set(&self, bit: u32) -> Option<&RwLock<SparseSet>> {
+        self.component(bit).sparse_set.as_ref()
+    }
+
+    /// Get an archetype by id.
+    pub(crate) fn archetype(&self, id: usize) -> &Archetype {
+        let archetype: *const Archetype = self.archetypes.read().get(id);
+        // SAFETY: the archetype has a stable address, see `Entities::component`.
+        unsafe { &*archetype }
+    }
+
+    /// Get the archetype for a set of components, creating it if it doesn't exist yet.
+    fn archetype_with(&self, mask: &RoaringBitmap) -> &Archetype {
+        let archetype: *const Archetype = {
+            let archetypes = self.archetypes.upgradable_read();
+            if let Some(archetype) = archetypes.find(mask) {
+                archetype
+            } else {
+                parking_lot::RwLockUpgradableReadGuard::upgrade(archetypes)
+                    .insert(mask, |bit| self.component(bit).prototype.empty_clone())
+            }
+        };
+        // SAFETY: the archetype has a stable address, see `Entities::component`.
+        unsafe { &*archetype }
+    }
+
+    /// All archetypes that currently exist.
+    pub(crate) fn archetypes(&self) -> Vec<&Archetype> {
+        let archetypes = self.archetypes.read();
+        archetypes
+            .iter()
+            .map(|archetype| {
+                let archetype: *const Archetype = archetype;
+                // SAFETY: the archetype has a stable address, see `Entities::component`.
+                unsafe { &*archetype }
+            })
+            .collect()
+    }
+
+    /// The bits of all components in the set, in the order of [`ComponentSet::type_ids`].
+    /// Fails with the first unregistered component.
+    fn bits_of<C: ComponentSet>(&self) -> Result<Vec<u32>, EntityError> {
+        let registry = self.registry.read();
+        C::type_ids()
+            .iter()
+            .zip(C::type_names())
+            .map(|(type_id, name)| {
+                registry
+                    .bit(type_id)
+                    .ok_or(EntityError::ComponentNotRegistered(name))
+            })
+            .collect()
+    }
+
+    /// Take a free entity slot or add a new one.
+    fn allocate(map: &mut Vec<EntityMeta>) -> usize {
+        if let Some((index, _)) = map.par_iter().enumerate().find_any(|(_, meta)| !meta.alive) {
+            index
+        } else {
+            map.push(EntityMeta::default());
+            map.len() - 1
         }
     }
 
-    pub(crate) fn create_entity_batch(
+    pub(crate) fn create_entity<C: ComponentSet>(
         &self,
-        components: impl ComponentSet,
-        mut num: usize,
-    ) -> Result<(), EntityError> {
+        components: C,
+    ) -> Result<Entity, EntityError> {
+        let bits = self.bits_of::<C>()?;
+        let mask: RoaringBitmap = bits.iter().copied().collect();
+        let archetype = self.archetype_with(&self.table_mask(&mask));
+        let mut rows = archetype.entities.write();
+        let mut map = self.map.write();
+
+        let index = Self::allocate(&mut map);
+        let meta = &mut map[index];
+        meta.alive = true;
+        meta.mask = mask;
+        meta.archetype = archetype.id();
+        meta.row = rows.len();
+        let entity = Entity::new(index, meta.generation);
+        rows.push(entity);
+
+        components.for_components(&mut PushComponents {
+            entities: self,
+            archetype,
+            entity,
+            tick: self.change_tick(),
+            bits: &bits,
+            visited: 0,
+            pushed: RoaringBitmap::new(),
+        });
+        Ok(entity)
+    }
+
+    pub(crate) fn create_entity_batch<C: ComponentSet + Clone>(
+        &self,
+        components: C,
+        num: usize,
+    ) -> Result<Vec<Entity>, EntityError> {
+        self.spawn_batch(std::iter::repeat_n(components, num))
+    }
+
+    /// Spawn an entity for every component set of the iterator while the archetype and entity map stay locked.
+    pub(crate) fn spawn_batch<C: ComponentSet>(
+        &self,
+        batch: impl IntoIterator<Item = C>,
+    ) -> Result<Vec<Entity>, EntityError> {
+        let bits = self.bits_of::<C>()?;
+        let mask: RoaringBitmap = bits.iter().copied().collect();
+        let archetype = self.archetype_with(&self.table_mask(&mask));
+        let mut rows = archetype.entities.write();
         let mut map = self.map.write();
-        let mut result = Ok(());
 
-        let reuse: Vec<usize> = map
+        let tick = self.change_tick();
+        let mut free: Vec<usize> = map
             .par_iter()
             .enumerate()
-            .filter(|(_, mask)| mask.is_empty())
+            .filter(|(_, meta)| !meta.alive)
             .map(|(index, _)| index)
             .collect();
 
-        let mut component_vecs = vec![];
-        components.for_components(|type_id, component| {
-            if let Some(component_vec) = self.components.get(&type_id) {
-                component_vecs.push((
-                    component_vec,
-                    component,
-                    *self.bit_masks.get(&type_id).unwrap(),
-                ));
+        Ok(batch
+            .into_iter()
+            .map(|components| {
+                let index = free.pop().unwrap_or_else(|| {
+                    map.push(EntityMeta::default());
+                    map.len() - 1
+                });
+                let meta = &mut map[index];
+                meta.alive = true;
+                meta.mask = mask.clone();
+                meta.archetype = archetype.id();
+                meta.row = rows.len();
+                let entity = Entity::new(index, meta.generation);
+                rows.push(entity);
+
+                components.for_components(&mut PushComponents {
+                    entities: self,
+                    archetype,
+                    entity,
+                    tick,
+                    bits: &bits,
+                    visited: 0,
+                    pushed: RoaringBitmap::new(),
+                });
+                entity
+            })
+            .collect())
+    }
+
+    pub(crate) fn get_bitmask(&self, type_id: &TypeId) -> Option<u32> {
+        self.registry.read().bit(type_id)
+    }
+
+    /// Check that the [`Entity`] handle refers to a living entity.
+    pub(crate) fn validate(&self, entity: Entity) -> Result<(), EntityError> {
+        Self::validate_in(&self.map.read(), entity)
+    }
+
+    fn validate_in(map: &[EntityMeta], entity: Entity) -> Result<(), EntityError> {
+        let meta = map
+            .get(entity.index())
+            .ok_or(EntityError::EntityDoesNotExist)?;
+        if !meta.alive || meta.generation != entity.generation() {
+            return Err(EntityError::StaleEntity);
+        }
+        Ok(())
+    }
+
+    /// Find the storage of a component of an entity. Has to be called while the entity map is locked.
+    fn location(
+        &self,
+        map: &[EntityMeta],
+        entity: Entity,
+        bit: u32,
+    ) -> Result<Location<'_>, EntityError> {
+        Self::validate_in(map, entity)?;
+        if let Some(sparse_set) = self.sparse_set(bit) {
+            return Ok(Location::SparseSet(sparse_set));
+        }
+        let meta = &map[entity.index()];
+        let column = self
+            .archetype(meta.archetype)
+            .columns
+            .get(&bit)
+            .ok_or(EntityError::ComponentDataDoesNotExist)?;
+        Ok(Location::Table(column, meta.row))
+    }
+
+    /// Lock the column storing a component of an entity for reading and get the entity's row.
+    fn read_column(
+        &self,
+        entity: Entity,
+        bit: u32,
+    ) -> Result<(MappedRwLockReadGuard<'_, Column>, usize), EntityError> {
+        let map = self.map.read();
+        Ok(match self.location(&map, entity, bit)? {
+            Location::Table(column, row) => (RwLockReadGuard::map(column.read(), |c| c), row),
+            Location::SparseSet(sparse_set) => {
+                let sparse_set = sparse_set.read();
+                let row = sparse_set
+                    .row(entity)
+                    .ok_or(EntityError::ComponentDataDoesNotExist)?;
+                (RwLockReadGuard::map(sparse_set, SparseSet::column), row)
+            }
+        })
+    }
+
+    /// Lock the column storing a component of an entity for writing, mark the component as changed and get the entity's row.
+    fn write_column(
+        &self,
+        entity: Entity,
+        bit: u32,
+    ) -> Result<(MappedRwLockWriteGuard<'_, Column>, usize), EntityError> {
+        let map = self.map.read();
+        let (mut column, row) = match self.location(&map, entity, bit)? {
+            Location::Table(column, row) => (RwLockWriteGuard::map(column.write(), |c| c), row),
+            Location::SparseSet(sparse_set) => {
+                let sparse_set = sparse_set.write();
+                let row = sparse_set
+                    .row(entity)
+                    .ok_or(EntityError::ComponentDataDoesNotExist)?;
+                (
+                    RwLockWriteGuard::map(sparse_set, SparseSet::column_mut),
+                    row,
+                )
+            }
+        };
+        drop(map);
+        column.set_changed(row, self.change_tick());
+        Ok((column, row))
+    }
+
+    pub(crate) fn component_ref_by_entity_id<T: Any + Send + Sync>(
+        &self,
+        entity: Entity,
+    ) -> Result<ComponentRef<'_, T>, EntityError> {
+        let (column, row) = self.read_column(entity, self.bit_of::<T>()?)?;
+        MappedRwLockReadGuard::try_map(column, |column| column.get::<T>(row))
+            .map(ComponentRef::new)
+            .map_err(|_| EntityError::ComponentDataDoesNotExist)
+    }
+
+    pub(crate) fn component_mut_by_entity_id<T: Any + Send + Sync>(
+        &self,
+        entity: Entity,
+    ) -> Result<ComponentMut<'_, T>, EntityError> {
+        let (column, row) = self.write_column(entity, self.bit_of::<T>()?)?;
+        MappedRwLockWriteGuard::try_map(column, |column| column.get_mut::<T>(row))
+            .map(ComponentMut::new)
+            .map_err(|_| EntityError::ComponentDataDoesNotExist)
+    }
+
+    pub(crate) fn component_ptr_by_entity_id(
+        &self,
+        entity: Entity,
+        id: ComponentId,
+    ) -> Result<ComponentPtr<'_>, EntityError> {
+        let (column, row) = self.read_column(entity, self.bit_of_id(id)?)?;
+        Ok(ComponentPtr::new(column, row))
+    }
+
+    pub(crate) fn component_ptr_mut_by_entity_id(
+        &self,
+        entity: Entity,
+        id: ComponentId,
+    ) -> Result<ComponentPtrMut<'_>, EntityError> {
+        let (column, row) = self.write_column(entity, self.bit_of_id(id)?)?;
+        Ok(ComponentPtrMut::new(column, row))
+    }
+
+    pub(crate) fn has_component_by_entity_id<T: Any + Send + Sync>(&self, entity: Entity) -> bool {
+        self.bit_of::<T>()
+            .is_ok_and(|bit| self.has_component(entity, bit))
+    }
+
+    pub(crate) fn has_component_id(&self, entity: Entity, id: ComponentId) -> bool {
+        self.bit_of_id(id)
+            .is_ok_and(|bit| self.has_component(entity, bit))
+    }
+
+    pub(crate) fn has_component(&self, entity: Entity, bit: u32) -> bool {
+        let map = self.map.read();
+        Self::validate_in(&map, entity).is_ok() && map[entity.index()].mask.contains(bit)
+    }
+
+    pub(crate) fn entity_ref(&self, entity: Entity) -> Result<EntityRef<'_>, EntityError> {
+        self.validate(entity)?;
+        Ok(EntityRef::new(entity, self))
+    }
+
+    /// The bits of all components of an entity.
+    pub(crate) fn mask(&self, entity: Entity) -> Result<RoaringBitmap, EntityError> {
+        let map = self.map.read();
+        Self::validate_in(&map, entity)?;
+        Ok(map[entity.index()].mask.clone())
+    }
+
+    /// Move an entity to the archetype with the components in `mask`.
+    /// Runs `then` on the destination archetype, entity map and new row while everything is still locked.
+    /// The removed component with the bit `take` is returned instead of being dropped.
+    fn move_entity<R>(
+        &self,
+        entity: Entity,
+        mask: impl Fn(&RoaringBitmap) -> RoaringBitmap,
+        take: Option<u32>,
+        then: impl FnOnce(&Archetype, &mut [EntityMeta], usize) -> R,
+    ) -> Result<(R, Option<Box<dyn Any + Send + Sync>>), EntityError> {
+        loop {
+            let (source, source_mask) = {
+                let map = self.map.read();
+                Self::validate_in(&map, entity)?;
+                let meta = &map[entity.index()];
+                (self.archetype(meta.archetype), meta.mask.clone())
+            };
+            let destination = self.archetype_with(&self.table_mask(&mask(&source_mask)));
+
+            // lock the rows of both archetypes in order of their ids
+            let (mut source_rows, mut destination_rows) = if source.id() < destination.id() {
+                let source_rows = source.entities.write();
+                (source_rows, Some(destination.entities.write()))
+            } else if source.id() > destination.id() {
+                let destination_rows = destination.entities.write();
+                (source.entities.write(), Some(destination_rows))
             } else {
-                result = Err(EntityError::ComponentNotRegistered);
+                (source.entities.write(), None)
+            };
+            let mut map = self.map.write();
+            Self::validate_in(&map, entity)?;
+            if map[entity.index()].archetype != source.id() {
+                // the entity was moved while the locks were released
+                continue;
             }
-        });
 
-        result?;
-
-        if reuse.len() <= num {
-            num -= reuse.len();
-            reuse.par_iter().for_each(|index| {
-                component_vecs
-                    .par_iter()
-                    .for_each(|(component_vec, component, _)| {
-                        *component_vec.write().get_mut(*index).unwrap() =
-                            Some(component.to_owned());
-                    });
-            });
-            reuse.iter().for_each(|index| {
-                component_vecs.iter().for_each(|(_, _, bit_mask)| {
-                    map[*index].insert(*bit_mask);
-                });
-            });
+            let row = map[entity.index()].row;
+            let Some(destination_rows) = destination_rows.as_mut() else {
+                return Ok((then(destination, &mut map, row), None));
+            };
 
-            for _ in 0..num {
-                self.components
-                    .par_iter()
-                    .for_each(|(_, components)| components.write().push(None));
-                map.push(RoaringBitmap::new());
-
-                let index = map.len() - 1;
-                component_vecs
-                    .par_iter()
-                    .for_each(|(component_vec, component, _)| {
-                        *component_vec.write().get_mut(index).unwrap() = Some(component.to_owned());
-                    });
-                component_vecs.iter().for_each(|(_, _, bit_mask)| {
-                    map[index].insert(*bit_mask);
-                });
+            let mut taken = None;
+            for (bit, column) in &source.columns {
+                let mut column = column.write();
+                if let Some(destination_column) = destination.columns.get(bit) {
+                    // SAFETY: both columns store the component with the same bit.
+                    unsafe { column.swap_remove_into(row, &mut destination_column.write()) };
+                } else if take == Some(*bit) {
+                    taken = Some(self.take_from_column(*bit, &mut column, row, entity));
+                } else {
+                    self.remove_from_column(*bit, &mut column, row, entity);
+                }
             }
-            Ok(())
-        } else {
-            reuse.par_iter().skip(reuse.len() - num).for_each(|index| {
-                component_vecs
-                    .par_iter()
-                    .for_each(|(component_vec, component, _)| {
-                        *component_vec.write().get_mut(*index).unwrap() =
-                            Some(component.to_owned());
-                    });
-            });
-            reuse.iter().skip(reuse.len() - num).for_each(|index| {
-                component_vecs.iter().for_each(|(_, _, bit_mask)| {
-                    map[*index].insert(*bit_mask);
-                });
-            });
-            Ok(())
+            Self::remove_row(&mut source_rows, &mut map, row);
+
+            let new_row = destination_rows.len();
+            destination_rows.push(entity);
+            let meta = &mut map[entity.index()];
+            meta.mask = mask(&meta.mask);
+            meta.archetype = destination.id();
+            meta.row = new_row;
+            return Ok((then(destination, &mut map, new_row), taken));
         }
     }
 
-    pub(crate) fn get_bitmask(&self, type_id: &TypeId) -> Option<&u32> {
-        self.bit_masks.get(type_id)
+    /// Swap remove a row from the archetype's entity list and update the row of the moved entity.
+    fn remove_row(rows: &mut Vec<Entity>, map: &mut [EntityMeta], row: usize) {
+        rows.swap_remove(row);
+        if let Some(moved) = rows.get(row) {
+            map[moved.index()].row = row;
+        }
+    }
+
+    /// Remove a component of an entity. The removed value is returned if `take` is set, otherwise it is dropped or kept in the removal log.
+    fn remove_component(
+        &self,
+        entity: Entity,
+        bit: u32,
+        take: bool,
+    ) -> Result<Option<Box<dyn Any + Send + Sync>>, EntityError> {
+        if let Some(sparse_set) = self.sparse_set(bit) {
+            let mut map = self.map.write();
+            Self::validate_in(&map, entity)?;
+            map[entity.index()].mask.remove(bit);
+            let mut sparse_set = sparse_set.write();
+            return Ok(sparse_set
+                .remove_with(entity, |column, row| {
+                    if take {
+                        Some(self.take_from_column(bit, column, row, entity))
+                    } else {
+                        self.remove_from_column(bit, column, row, entity);
+                        None
+                    }
+                })
+                .flatten());
+        }
+
+        let (_, taken) = self.move_entity(
+            entity,
+            |source| {
+                let mut destination = source.clone();
+                destination.remove(bit);
+                destination
+            },
+            take.then_some(bit),
+            |_, _, _| {},
+        )?;
+        Ok(taken)
     }
 
     pub(crate) fn remove_component_by_entity_id<T: Any + Send + Sync>(
         &self,
-        index: usize,
+        entity: Entity,
     ) -> Result<(), EntityError> {
-        let type_id = TypeId::of::<T>();
-        let mask = if let Some(mask) = self.bit_masks.get(&type_id) {
-            mask
-        } else {
-            return Err(EntityError::ComponentNotRegistered);
-        };
+        self.remove_component(entity, self.bit_of::<T>()?, false)?;
+        Ok(())
+    }
 
-        let mut map = self.map.write();
-        map[index].remove(*mask);
+    pub(crate) fn remove_component_by_id(
+        &self,
+        entity: Entity,
+        id: ComponentId,
+    ) -> Result<(), EntityError> {
+        self.remove_component(entity, self.bit_of_id(id)?, false)?;
         Ok(())
     }
 
-    pub(crate) fn add_component_by_entity_id(
+    /// Remove a component of an entity and return it. Returns [`None`] if the entity doesn't have the component.
+    pub(crate) fn take_component_by_entity_id<T: Any + Send + Sync>(
         &self,
-        data: impl Any + Send + Sync,
-        index: usize,
+        entity: Entity,
+    ) -> Result<Option<T>, EntityError> {
+        Ok(self
+            .remove_component(entity, self.bit_of::<T>()?, true)?
+            .and_then(|value| value.downcast().ok())
+            .map(|value| *value))
+    }
+
+    /// Add a component to an entity. Unregistered components are registered with [`StorageType::Table`] first.
+    pub(crate) fn add_component_by_entity_id<T: Any + Send + Sync>(
+        &self,
+        data: T,
+        entity: Entity,
     ) -> Result<(), EntityError> {
-        let type_id = data.type_id();
-        let mask = if let Some(mask) = self.bit_masks.get(&type_id) {
-            mask
-        } else {
-            return Err(EntityError::ComponentNotRegistered);
-        };
-        self.map.write()[index].insert(*mask);
+        self.validate(entity)?;
+        let bit = self.register_component::<T>();
+        self.insert_component(entity, bit, |column, row, tick| match row {
+            Some(row) => column.replace(row, data, tick),
+            None => column.push(data, tick),
+        })
+    }
 
-        let components = self.components.get(&type_id).unwrap();
-        components.write()[index] = Some(Arc::new(RwLock::new(data)));
-        Ok(())
+    /// Add the component `value` points to to an entity.
+    ///
+    /// # Safety
+    /// `value` has to point to a valid component with the registered layout, which is moved into the column.
+    pub(crate) unsafe fn add_component_by_id(
+        &self,
+        entity: Entity,
+        id: ComponentId,
+        value: *const u8,
+    ) -> Result<(), EntityError> {
+        let bit = self.bit_of_id(id)?;
+        self.insert_component(entity, bit, |column, row, tick| match row {
+            Some(row) => column.replace_ptr(row, value, tick),
+            None => column.push_ptr(value, tick),
+        })
     }
 
-    pub(crate) fn delete_entity_by_id(&self, index: usize) -> Result<(), EntityError> {
-        if let Some(map) = self.map.write().get_mut(index) {
-            map.clear();
-        } else {
-            return Err(EntityError::EntityDoesNotExist);
+    /// Add a component that is a blob of bytes to an entity.
+    pub(crate) fn add_component_bytes(
+        &self,
+        entity: Entity,
+        id: ComponentId,
+        bytes: &[u8],
+    ) -> Result<(), EntityError> {
+        let bit = self.bit_of_id(id)?;
+        let prototype = &self.component(bit).prototype;
+        if !prototype.is_dynamic()
+            || prototype.has_drop()
+            || prototype.item_layout() != std::alloc::Layout::for_value(bytes)
+        {
+            return Err(EntityError::InvalidComponentBytes);
+        }
+        // SAFETY: the component is a byte blob of the same layout without drop glue.
+        unsafe { self.add_component_by_id(entity, id, bytes.as_ptr()) }
+    }
+
+    /// Add a component to an entity, moving it to another archetype if necessary.
+    /// `write` gets the column, the row of an already present component or [`None`] if it has to push the component, and the change tick.
+    fn insert_component(
+        &self,
+        entity: Entity,
+        bit: u32,
+        write: impl FnOnce(&mut Column, Option<usize>, u32),
+    ) -> Result<(), EntityError> {
+        let tick = self.change_tick();
+        if let Some(sparse_set) = self.sparse_set(bit) {
+            let mut map = self.map.write();
+            Self::validate_in(&map, entity)?;
+            map[entity.index()].mask.insert(bit);
+            sparse_set
+                .write()
+                .insert_with(entity, |column, row| write(column, row, tick));
+            return Ok(());
         }
+
+        self.move_entity(
+            entity,
+            |source| {
+                let mut destination = source.clone();
+                destination.insert(bit);
+                destination
+            },
+            None,
+            |archetype, _, row| {
+                let mut column = archetype.columns[&bit].write();
+                let present = (row < column.len()).then_some(row);
+                write(&mut column, present, tick);
+            },
+        )?;
         Ok(())
     }
 
-    pub(crate) fn query(&self) -> Query {
-        Query::new(self)
+    pub(crate) fn delete_entity_by_id(&self, entity: Entity) -> Result<(), EntityError> {
+        loop {
+            let archetype = {
+                let map = self.map.read();
+                Self::validate_in(&map, entity)?;
+                self.archetype(map[entity.index()].archetype)
+            };
+            let mut rows = archetype.entities.write();
+            let mut map = self.map.write();
+            Self::validate_in(&map, entity)?;
+            if map[entity.index()].archetype != archetype.id() {
+                continue;
+            }
+
+            let row = map[entity.index()].row;
+            for (bit, column) in &archetype.columns {
+                self.remove_from_column(*bit, &mut column.write(), row, entity);
+            }
+            for bit in &map[entity.index()].mask & &self.sparse_bits() {
+                self.remove_from_sparse_set(bit, entity);
+            }
+            Self::remove_row(&mut rows, &mut map, row);
+
+            let meta = &mut map[entity.index()];
+            meta.mask.clear();
+            meta.alive = false;
+            meta.generation = meta.generation.wrapping_add(1);
+            return Ok(());
+        }
     }
 }
 
@@ -216,156 +905,511 @@ impl Entities {
 mod test {
     use super::*;
 
+    fn table<'a>(entities: &'a Entities, bits: &[u32]) -> &'a Archetype {
+        let id = entities
+            .archetypes
+            .read()
+            .find(&bits.iter().copied().collect())
+            .unwrap()
+            .id();
+        entities.archetype(id)
+    }
+
     #[test]
     fn register_component() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         let type_id = TypeId::of::<Health>();
-        let health_components = entities.components.get(&type_id).unwrap();
-        assert_eq!(health_components.read().len(), 0);
+        let bit = entities.get_bitmask(&type_id).unwrap();
+        assert_eq!(entities.component(bit).prototype.len(), 0);
     }
 
     #[test]
     fn update_component_masks() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
         let type_id = TypeId::of::<Speed>();
-        let mask = entities.bit_masks.get(&type_id).unwrap();
-        assert_eq!(*mask, 1);
+        let mask = entities.get_bitmask(&type_id).unwrap();
+        assert_eq!(mask, 1);
+    }
+
+    #[test]
+    fn register_concurrently() {
+        let entities = Entities::default();
+        let bits: Vec<u32> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| entities.register_component::<Health>()))
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+        assert!(bits.iter().all(|bit| *bit == 0));
+        assert_eq!(entities.register_component::<Speed>(), 1);
+    }
+
+    #[test]
+    fn register_on_insert() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+
+        entities
+            .add_component_by_entity_id(Speed(10), entity)
+            .unwrap();
+        assert_eq!(entities.get_bitmask(&TypeId::of::<Speed>()), Some(1));
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Speed>(entity)
+                .unwrap()
+                .0,
+            10
+        );
+        assert_eq!(table(&entities, &[0, 1]).entities.read().len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered with a different layout")]
+    fn conflicting_dynamic_component() {
+        let entities = Entities::default();
+        entities.register_dynamic_component(ComponentDescriptor::bytes("Position", 8));
+        entities.register_dynamic_component(ComponentDescriptor::bytes("Position", 12));
+    }
+
+    #[test]
+    #[should_panic(expected = "already registered with a different storage type")]
+    fn conflicting_storage() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+        // registers the component with table storage
+        entities
+            .add_component_by_entity_id(Speed(10), entity)
+            .unwrap();
+        entities.register_component_with_storage::<Speed>(StorageType::Table);
+        entities.register_component_with_storage::<Speed>(StorageType::SparseSet);
+    }
+
+    #[test]
+    fn dynamic_component() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        let position =
+            entities.register_dynamic_component(ComponentDescriptor::bytes("Position", 8));
+        let selected = entities.register_dynamic_component(
+            ComponentDescriptor::bytes("Selected", 0).with_storage(StorageType::SparseSet),
+        );
+        assert_eq!(position, ComponentId(1));
+        assert_eq!(
+            entities.register_dynamic_component(ComponentDescriptor::bytes("Position", 8)),
+            position
+        );
+        assert_eq!(entities.component_id_by_name("Selected"), Some(selected));
+
+        let entity = entities.create_entity((Health(100),)).unwrap();
+        entities
+            .add_component_bytes(entity, position, &[1, 2, 3, 4, 5, 6, 7, 8])
+            .unwrap();
+        entities.add_component_bytes(entity, selected, &[]).unwrap();
+        assert_eq!(
+            entities.add_component_bytes(entity, position, &[1]),
+            Err(EntityError::InvalidComponentBytes)
+        );
+        assert_eq!(
+            entities.add_component_bytes(entity, ComponentId(0), &[0; 4]),
+            Err(EntityError::InvalidComponentBytes)
+        );
+        assert_eq!(
+            entities.add_component_bytes(entity, ComponentId(3), &[]),
+            Err(EntityError::ComponentIdNotRegistered)
+        );
+
+        assert_eq!(table(&entities, &[0, 1]).entities.read().len(), 1);
+        assert!(entities.has_component_id(entity, selected));
+        let bytes = entities
+            .component_ptr_by_entity_id(entity, position)
+            .unwrap();
+        assert_eq!(unsafe { bytes.as_bytes() }, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        drop(bytes);
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(entity)
+                .unwrap()
+                .0,
+            100
+        );
+
+        entities.remove_component_by_id(entity, position).unwrap();
+        entities.remove_component_by_id(entity, selected).unwrap();
+        assert!(!entities.has_component_id(entity, position));
+        assert!(!entities.has_component_id(entity, selected));
+        assert_eq!(table(&entities, &[0]).entities.read().len(), 1);
     }
 
     #[test]
     fn create_entity() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
         entities.create_entity((Health(100),)).unwrap();
 
-        let health = entities.components.get(&TypeId::of::<Health>()).unwrap();
-        let speed = entities.components.get(&TypeId::of::<Speed>()).unwrap();
+        let table = table(&entities, &[0]);
+        let health = table.columns.get(&0).unwrap();
 
         assert_eq!(health.read().len(), 1);
-        assert_eq!(speed.read().len(), 1);
-        assert!(health.read()[0].is_some());
-        assert!(speed.read()[0].is_none());
+        assert!(health.read().get::<Health>(0).is_some());
+        assert!(!table.columns.contains_key(&1));
     }
 
     #[test]
     fn create_entity_batch() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
 
-        entities.create_entity_batch((Health(10),), 100).unwrap();
-        {
-            let health = entities.components.get(&TypeId::of::<Health>()).unwrap();
-            assert_eq!(health.read().len(), 100);
-        }
-        for i in 0..50 {
-            entities.delete_entity_by_id(i).unwrap();
+        let batch = entities.create_entity_batch((Health(10),), 100).unwrap();
+        let health = table(&entities, &[0]).columns.get(&0).unwrap();
+        assert_eq!(health.read().len(), 100);
+        for entity in &batch[..50] {
+            entities.delete_entity_by_id(*entity).unwrap();
         }
         entities.create_entity_batch((Health(10),), 10).unwrap();
-        assert!(entities.map.read()[39].is_empty());
+        assert!(entities.map.read()[39].mask.is_empty());
         entities.create_entity_batch((Health(10),), 60).unwrap();
-        assert!(!entities.map.read()[0].is_empty());
-        let health = entities.components.get(&TypeId::of::<Health>()).unwrap();
+        assert!(!entities.map.read()[0].mask.is_empty());
         assert_eq!(health.read().len(), 120);
     }
 
+    #[test]
+    fn spawn_batch() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+
+        let batch = entities
+            .spawn_batch((0..10).map(|health| (Health(health),)))
+            .unwrap();
+        entities.delete_entity_by_id(batch[3]).unwrap();
+        let reused = entities
+            .spawn_batch([(Health(20),), (Health(30),)])
+            .unwrap();
+
+        assert_eq!(reused[0].index(), 3);
+        assert_eq!(reused[1].index(), 10);
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(batch[5])
+                .unwrap()
+                .0,
+            5
+        );
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(reused[1])
+                .unwrap()
+                .0,
+            30
+        );
+    }
+
+    #[test]
+    fn independent_batch_components() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        let batch = entities.create_entity_batch((Health(10),), 3).unwrap();
+
+        entities
+            .component_mut_by_entity_id::<Health>(batch[0])
+            .unwrap()
+            .0 = 0;
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(batch[1])
+                .unwrap()
+                .0,
+            10
+        );
+    }
+
     #[test]
     fn entity_with_component() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
         entities.create_entity((Health(100), Speed(15))).unwrap();
 
-        let health = &entities
-            .components
-            .get(&TypeId::of::<Health>())
-            .unwrap()
-            .read()[0];
-        let health_borrowed = health.as_ref().unwrap().read();
-        let health_downcast = health_borrowed.downcast_ref::<Health>().unwrap();
+        let table = table(&entities, &[0, 1]);
+        let health = table.columns.get(&0).unwrap().read();
+        let health_downcast = health.get::<Health>(0).unwrap();
 
-        let speed = &entities
-            .components
-            .get(&TypeId::of::<Speed>())
-            .unwrap()
-            .read()[0];
-        let speed_borrowed = speed.as_ref().unwrap().read();
-        let speed_downcast = speed_borrowed.downcast_ref::<Speed>().unwrap();
+        let speed = table.columns.get(&1).unwrap().read();
+        let speed_downcast = speed.get::<Speed>(0).unwrap();
         assert_eq!(health_downcast.0, 100);
         assert_eq!(speed_downcast.0, 15);
     }
 
     #[test]
     fn update_entity_map() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
         entities.create_entity((Health(100), Speed(15))).unwrap();
         entities.create_entity((Health(100),)).unwrap();
 
         let entity_map = entities.map.read();
-        assert!(entity_map[0].contains(0) && entity_map[0].contains(1));
-        assert!(entity_map[1].contains(0));
+        assert!(entity_map[0].mask.contains(0) && entity_map[0].mask.contains(1));
+        assert!(entity_map[1].mask.contains(0));
     }
 
     #[test]
     fn remove_component() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
 
-        entities.create_entity((Health(10), Speed(50))).unwrap();
+        let entity = entities.create_entity((Health(10), Speed(50))).unwrap();
 
-        entities.remove_component_by_entity_id::<Health>(0).unwrap();
+        entities
+            .remove_component_by_entity_id::<Health>(entity)
+            .unwrap();
 
-        assert!(entities.map.read()[0].contains(1) && !entities.map.read()[0].contains(0));
+        assert!(
+            entities.map.read()[0].mask.contains(1) && !entities.map.read()[0].mask.contains(0)
+        );
     }
 
     #[test]
     fn add_component() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
         entities.register_component::<Speed>();
-        entities.create_entity((Health(100),)).unwrap();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+
+        entities
+            .add_component_by_entity_id(Speed(50), entity)
+            .unwrap();
+
+        assert!(entities.map.read()[0].mask.contains_range(1..2));
+    }
+
+    #[test]
+    fn move_entity_between_archetypes() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        entities.register_component::<Speed>();
+        let first = entities.create_entity((Health(100),)).unwrap();
+        let second = entities.create_entity((Health(50),)).unwrap();
+
+        entities
+            .add_component_by_entity_id(Speed(5), first)
+            .unwrap();
+
+        let health = table(&entities, &[0]).columns.get(&0).unwrap();
+        assert_eq!(health.read().len(), 1);
+        assert_eq!(health.read().get::<Health>(0).unwrap().0, 50);
+        assert_eq!(entities.map.read()[second.index()].row, 0);
+
+        let moved = table(&entities, &[0, 1]);
+        assert_eq!(*moved.entities.read(), vec![first]);
+        assert_eq!(
+            moved
+                .columns
+                .get(&0)
+                .unwrap()
+                .read()
+                .get::<Health>(0)
+                .unwrap()
+                .0,
+            100
+        );
+
+        entities
+            .remove_component_by_entity_id::<Speed>(first)
+            .unwrap();
+        assert!(moved.entities.read().is_empty());
+        assert_eq!(health.read().len(), 2);
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(first)
+                .unwrap()
+                .0,
+            100
+        );
+    }
+
+    #[test]
+    fn sparse_set_component() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        entities.register_component_with_storage::<Speed>(StorageType::SparseSet);
+        let entity = entities.create_entity((Health(100), Speed(10))).unwrap();
+        let other = entities.create_entity((Health(50),)).unwrap();
+
+        entities
+            .add_component_by_entity_id(Speed(20), other)
+            .unwrap();
+        entities
+            .remove_component_by_entity_id::<Speed>(entity)
+            .unwrap();
+
+        let table = table(&entities, &[0]);
+        assert_eq!(*table.entities.read(), vec![entity, other]);
+        assert!(!entities.map.read()[0].mask.contains(1));
+        assert!(entities.map.read()[1].mask.contains(1));
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Speed>(other)
+                .unwrap()
+                .0,
+            20
+        );
+        assert!(matches!(
+            entities.component_ref_by_entity_id::<Speed>(entity),
+            Err(EntityError::ComponentDataDoesNotExist)
+        ));
+
+        entities.delete_entity_by_id(other).unwrap();
+        assert!(!entities.sparse_set(1).unwrap().read().contains(other));
+    }
+
+    #[test]
+    fn record_removals() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        entities.register_component_with_storage::<Speed>(StorageType::SparseSet);
+        entities.track_removed_values::<Health>().unwrap();
+        let first = entities.create_entity((Health(100), Speed(10))).unwrap();
+        let second = entities.create_entity((Health(50), Speed(20))).unwrap();
+
+        entities
+            .remove_component_by_entity_id::<Health>(first)
+            .unwrap();
+        entities
+            .remove_component_by_entity_id::<Speed>(first)
+            .unwrap();
+        entities.delete_entity_by_id(second).unwrap();
+
+        let health = entities.removed::<Health>().unwrap();
+        assert_eq!(
+            health
+                .iter()
+                .map(|(entity, health)| (entity, health.map(|health| health.0)))
+                .collect::<Vec<_>>(),
+            vec![(first, Some(100)), (second, Some(50))]
+        );
+        let speed = entities.removed::<Speed>().unwrap();
+        assert_eq!(
+            speed
+                .iter()
+                .map(|(entity, speed)| (entity, speed.is_some()))
+                .collect::<Vec<_>>(),
+            vec![(first, false), (second, false)]
+        );
+    }
+
+    #[test]
+    fn take_component() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        entities.register_component_with_storage::<Speed>(StorageType::SparseSet);
+        let entity = entities.create_entity((Health(100), Speed(10))).unwrap();
 
-        entities.add_component_by_entity_id(Speed(50), 0).unwrap();
+        let health = entities.take_component_by_entity_id::<Health>(entity);
+        assert_eq!(health.unwrap().map(|health| health.0), Some(100));
+        let speed = entities.take_component_by_entity_id::<Speed>(entity);
+        assert_eq!(speed.unwrap().map(|speed| speed.0), Some(10));
+        assert!(entities
+            .take_component_by_entity_id::<Health>(entity)
+            .unwrap()
+            .is_none());
 
-        assert!(entities.map.read()[0].contains_range(1..2));
+        assert_eq!(table(&entities, &[0]).columns[&0].read().len(), 0);
+        assert!(matches!(
+            entities.component_ref_by_entity_id::<Health>(entity),
+            Err(EntityError::ComponentDataDoesNotExist)
+        ));
+        assert!(matches!(
+            entities.component_ref_by_entity_id::<Speed>(entity),
+            Err(EntityError::ComponentDataDoesNotExist)
+        ));
+        assert_eq!(entities.removed::<Health>().unwrap().entities().count(), 1);
     }
 
     #[test]
     fn delete_entity_by_id() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
-        entities.create_entity((Health(100),)).unwrap();
-        entities.delete_entity_by_id(0).unwrap();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+        entities.delete_entity_by_id(entity).unwrap();
 
-        assert!(entities.map.read()[0].is_empty());
+        assert!(entities.map.read()[0].mask.is_empty());
     }
 
     #[test]
     fn reuse_deleted_entity_columns() {
-        let mut entities = Entities::default();
+        let entities = Entities::default();
         entities.register_component::<Health>();
-        entities.create_entity((Health(100),)).unwrap();
+        let entity = entities.create_entity((Health(100),)).unwrap();
         entities.create_entity((Health(50),)).unwrap();
-        entities.delete_entity_by_id(0).unwrap();
-        entities.create_entity((Health(25),)).unwrap();
+        entities.delete_entity_by_id(entity).unwrap();
+        let reused = entities.create_entity((Health(25),)).unwrap();
 
-        let health = &entities
-            .components
-            .get(&TypeId::of::<Health>())
-            .unwrap()
-            .read()[0];
-        let health_borrowed = health.as_ref().unwrap().read();
-        let health_downcast = health_borrowed.downcast_ref::<Health>().unwrap();
-        assert!(entities.map.read()[0].contains(0));
+        let health = table(&entities, &[0]).columns.get(&0).unwrap().read();
+        let row = entities.map.read()[reused.index()].row;
+        let health_downcast = health.get::<Health>(row).unwrap();
+        assert!(entities.map.read()[0].mask.contains(0));
+        assert_eq!(health.len(), 2);
         assert_eq!(health_downcast.0, 25);
+        assert_eq!(reused.index(), entity.index());
+        assert_ne!(reused.generation(), entity.generation());
+    }
+
+    #[test]
+    fn reject_stale_entity() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+        entities.delete_entity_by_id(entity).unwrap();
+        entities.create_entity((Health(50),)).unwrap();
+
+        assert!(matches!(
+            entities.delete_entity_by_id(entity),
+            Err(EntityError::StaleEntity)
+        ));
+        assert!(matches!(
+            entities.add_component_by_entity_id(Health(10), entity),
+            Err(EntityError::StaleEntity)
+        ));
+        assert!(matches!(
+            entities.remove_component_by_entity_id::<Health>(entity),
+            Err(EntityError::StaleEntity)
+        ));
+    }
+
+    #[test]
+    fn component_by_entity_id() {
+        let entities = Entities::default();
+        entities.register_component::<Health>();
+        entities.register_component::<Speed>();
+        let entity = entities.create_entity((Health(100),)).unwrap();
+
+        *entities
+            .component_mut_by_entity_id::<Health>(entity)
+            .unwrap() = Health(50);
+        assert_eq!(
+            entities
+                .component_ref_by_entity_id::<Health>(entity)
+                .unwrap()
+                .0,
+            50
+        );
+        assert!(matches!(
+            entities.component_ref_by_entity_id::<Speed>(entity),
+            Err(EntityError::ComponentDataDoesNotExist)
+        ));
     }
 
+    #[derive(Clone)]
     struct Health(u32);
     struct Speed(u32);
 }
@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, HashMap};
+
+use parking_lot::RwLock;
+use roaring::RoaringBitmap;
+
+use super::{column::Column, entity::Entity};
+
+/// A table storing all entities with the same set of components.
+/// Every component has its own [`Column`], the rows of all columns belong to the entity in the same row of `entities`.
+#[derive(Debug)]
+pub struct Archetype {
+    id: usize,
+    mask: RoaringBitmap,
+    pub(crate) entities: RwLock<Vec<Entity>>,
+    pub(crate) columns: BTreeMap<u32, RwLock<Column>>,
+}
+
+impl Archetype {
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    /// The component bits of the entities stored in this archetype.
+    pub(crate) fn mask(&self) -> &RoaringBitmap {
+        &self.mask
+    }
+}
+
+/// All archetypes of an [`Entities`](super::Entities) struct. Archetypes are never removed.
+#[derive(Debug, Default)]
+pub(crate) struct Archetypes {
+    // Boxed for stable addresses, see `Entities::component`.
+    #[allow(clippy::vec_box)]
+    tables: Vec<Box<Archetype>>,
+    index: HashMap<Vec<u32>, usize>,
+}
+
+impl Archetypes {
+    pub(crate) fn get(&self, id: usize) -> &Archetype {
+        &self.tables[id]
+    }
+
+    pub(crate) fn find(&self, mask: &RoaringBitmap) -> Option<&Archetype> {
+        self.index
+            .get(&mask.iter().collect::<Vec<u32>>())
+            .map(|id| self.get(*id))
+    }
+
+    /// Create a new archetype. `prototype` creates an empty column for a component bit.
+    pub(crate) fn insert(
+        &mut self,
+        mask: &RoaringBitmap,
+        prototype: impl Fn(u32) -> Column,
+    ) -> &Archetype {
+        let id = self.tables.len();
+        self.tables.push(Box::new(Archetype {
+            id,
+            mask: mask.clone(),
+            entities: RwLock::new(vec![]),
+            columns: mask
+                .iter()
+                .map(|bit| (bit, RwLock::new(prototype(bit))))
+                .collect(),
+        }));
+        self.index.insert(mask.iter().collect(), id);
+        self.get(id)
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Archetype> {
+        self.tables.iter().map(|archetype| &**archetype)
+    }
+}
@@ -0,0 +1,510 @@
+use std::{
+    alloc::{self, Layout},
+    any::{Any, TypeId},
+    cell::UnsafeCell,
+    ptr::{self, NonNull},
+};
+
+use super::tick::ComponentTicks;
+
+/// Moves a component out of the column into a box.
+type TakeFn = unsafe fn(*mut u8) -> Box<dyn Any + Send + Sync>;
+
+/// Type-erased, contiguous storage for one component type.
+/// Every row belongs to one entity of the owning table.
+/// Components defined at runtime have no [`TypeId`] and can only be accessed through pointers.
+pub struct Column {
+    type_id: Option<TypeId>,
+    item_layout: Layout,
+    drop: Option<unsafe fn(*mut u8)>,
+    take: Option<TakeFn>,
+    data: NonNull<u8>,
+    capacity: usize,
+    len: usize,
+    ticks: Vec<UnsafeCell<ComponentTicks>>,
+}
+
+// SAFETY: Columns are only created for component types that are `Send + Sync`.
+// Components defined at runtime are only accessed through raw pointers, whose users have to uphold this.
+unsafe impl Send for Column {}
+unsafe impl Sync for Column {}
+
+unsafe fn drop_ptr<T>(ptr: *mut u8) {
+    ptr.cast::<T>().drop_in_place();
+}
+
+unsafe fn take_ptr<T: Any + Send + Sync>(ptr: *mut u8) -> Box<dyn Any + Send + Sync> {
+    Box::new(ptr.cast::<T>().read())
+}
+
+impl Column {
+    pub(crate) fn new<T: Any + Send + Sync>() -> Self {
+        let mut column = Self::new_dynamic(
+            Layout::new::<T>(),
+            std::mem::needs_drop::<T>().then_some(drop_ptr::<T> as unsafe fn(*mut u8)),
+        );
+        column.type_id = Some(TypeId::of::<T>());
+        column.take = Some(take_ptr::<T>);
+        column
+    }
+
+    /// Create a column for a component defined at runtime by its layout and drop function.
+    pub(crate) fn new_dynamic(item_layout: Layout, drop: Option<unsafe fn(*mut u8)>) -> Self {
+        Self {
+            type_id: None,
+            item_layout,
+            drop,
+            take: None,
+            data: dangling(item_layout),
+            capacity: if item_layout.size() == 0 {
+                usize::MAX
+            } else {
+                0
+            },
+            len: 0,
+            ticks: vec![],
+        }
+    }
+
+    /// Create an empty column for the same component type.
+    pub(crate) fn empty_clone(&self) -> Self {
+        Self {
+            type_id: self.type_id,
+            item_layout: self.item_layout,
+            drop: self.drop,
+            take: self.take,
+            data: dangling(self.item_layout),
+            capacity: if self.item_layout.size() == 0 {
+                usize::MAX
+            } else {
+                0
+            },
+            len: 0,
+            ticks: vec![],
+        }
+    }
+
+    /// Number of rows in the column.
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The layout of a single component.
+    pub(crate) fn item_layout(&self) -> Layout {
+        self.item_layout
+    }
+
+    /// Check if the column stores a component defined at runtime.
+    pub(crate) fn is_dynamic(&self) -> bool {
+        self.type_id.is_none()
+    }
+
+    /// Check if the component has to be dropped.
+    pub(crate) fn has_drop(&self) -> bool {
+        self.drop.is_some()
+    }
+
+    fn reserve_one(&mut self) {
+        if self.len < self.capacity {
+            return;
+        }
+        let new_capacity = (self.capacity * 2).max(4);
+        let new_layout = array_layout(self.item_layout, new_capacity);
+        let data = if self.capacity == 0 {
+            // SAFETY: the layout has a non-zero size, zero-sized items never grow.
+            unsafe { alloc::alloc(new_layout) }
+        } else {
+            // SAFETY: `data` was allocated with the layout of the current capacity.
+            unsafe {
+                alloc::realloc(
+                    self.data.as_ptr(),
+                    array_layout(self.item_layout, self.capacity),
+                    new_layout.size(),
+                )
+            }
+        };
+        self.data = NonNull::new(data).unwrap_or_else(|| alloc::handle_alloc_error(new_layout));
+        self.capacity = new_capacity;
+    }
+
+    fn ptr(&self, row: usize) -> *mut u8 {
+        // SAFETY: callers only pass rows up to `capacity`, which are inside the allocation.
+        unsafe { self.data.as_ptr().add(row * stride(self.item_layout)) }
+    }
+
+    /// Add a component as the last row, added at `tick`.
+    pub(crate) fn push<T: Any>(&mut self, value: T, tick: u32) {
+        assert_eq!(self.type_id, Some(TypeId::of::<T>()));
+        self.reserve_one();
+        // SAFETY: the type matches and the row is past the end.
+        unsafe { self.ptr(self.len).cast::<T>().write(value) };
+        self.ticks.push(UnsafeCell::new(ComponentTicks::new(tick)));
+        self.len += 1;
+    }
+
+    /// Copy a component from `value` to the last row, added at `tick`.
+    ///
+    /// # Safety
+    /// `value` has to point to a valid component of this column, which is moved into the column.
+    pub(crate) unsafe fn push_ptr(&mut self, value: *const u8, tick: u32) {
+        self.reserve_one();
+        ptr::copy_nonoverlapping(value, self.ptr(self.len), self.item_layout.size());
+        self.ticks.push(UnsafeCell::new(ComponentTicks::new(tick)));
+        self.len += 1;
+    }
+
+    /// Replace the component in `row`, dropping the previous one. The component is marked as changed at `tick`.
+    pub(crate) fn replace<T: Any>(&mut self, row: usize, value: T, tick: u32) {
+        *self.get_mut(row).unwrap() = value;
+        self.set_changed(row, tick);
+    }
+
+    /// Replace the component in `row` with the one `value` points to, dropping the previous one.
+    /// The component is marked as changed at `tick`.
+    ///
+    /// # Safety
+    /// `value` has to point to a valid component of this column, which is moved into the column.
+    pub(crate) unsafe fn replace_ptr(&mut self, row: usize, value: *const u8, tick: u32) {
+        assert!(row < self.len);
+        if let Some(drop) = self.drop {
+            drop(self.ptr(row));
+        }
+        ptr::copy_nonoverlapping(value, self.ptr(row), self.item_layout.size());
+        self.set_changed(row, tick);
+    }
+
+    /// Remove the component in `row` by moving the last row into it and drop it.
+    /// The component is dropped last, after it was swapped past the end,
+    /// so the column stays consistent if the drop panics and the component is leaked.
+    pub(crate) fn swap_remove(&mut self, row: usize) {
+        assert!(row < self.len);
+        self.len -= 1;
+        if row != self.len {
+            // SAFETY: both rows are occupied and differ.
+            unsafe {
+                ptr::swap_nonoverlapping(self.ptr(row), self.ptr(self.len), self.item_layout.size())
+            };
+        }
+        self.ticks.swap_remove(row);
+        if let Some(drop) = self.drop {
+            // SAFETY: the removed component is past the end now, so it isn't accessed again.
+            unsafe { drop(self.ptr(self.len)) };
+        }
+    }
+
+    /// Remove the component in `row` by moving the last row into it and return it.
+    /// Panics for components defined at runtime.
+    pub(crate) fn swap_remove_boxed(&mut self, row: usize) -> Box<dyn Any + Send + Sync> {
+        assert!(row < self.len);
+        let take = self
+            .take
+            .expect("only components with a Rust type can be boxed");
+        self.len -= 1;
+        // SAFETY: the row is occupied and gets overwritten by the last row afterwards.
+        let value = unsafe {
+            let value = take(self.ptr(row));
+            self.move_last_to(row);
+            value
+        };
+        self.ticks.swap_remove(row);
+        value
+    }
+
+    /// Move the component in `row` to the end of `other` and fill the gap with the last row.
+    ///
+    /// # Safety
+    /// `other` has to store the same component type.
+    pub(crate) unsafe fn swap_remove_into(&mut self, row: usize, other: &mut Column) {
+        assert!(row < self.len);
+        debug_assert_eq!(self.type_id, other.type_id);
+        other.reserve_one();
+        self.len -= 1;
+        ptr::copy_nonoverlapping(self.ptr(row), other.ptr(other.len), self.item_layout.size());
+        other.len += 1;
+        self.move_last_to(row);
+        other.ticks.push(self.ticks.swap_remove(row));
+    }
+
+    /// Move the row past the end into `row`, if they differ.
+    unsafe fn move_last_to(&mut self, row: usize) {
+        if row != self.len {
+            ptr::copy_nonoverlapping(self.ptr(self.len), self.ptr(row), self.item_layout.size());
+        }
+    }
+
+    /// The ticks of the component in `row`.
+    pub(crate) fn ticks(&self, row: usize) -> Option<ComponentTicks> {
+        // SAFETY: ticks are only written through `&mut self` or by the holder of exclusive access.
+        self.ticks.get(row).map(|ticks| unsafe { *ticks.get() })
+    }
+
+    /// Mark the component in `row` as changed at `tick`.
+    pub(crate) fn set_changed(&mut self, row: usize, tick: u32) {
+        if let Some(ticks) = self.ticks.get_mut(row) {
+            ticks.get_mut().changed = tick;
+        }
+    }
+
+    pub(crate) fn get<T: Any>(&self, row: usize) -> Option<&T> {
+        assert_eq!(self.type_id, Some(TypeId::of::<T>()));
+        // SAFETY: the type matches and the row is occupied.
+        (row < self.len).then(|| unsafe { &*self.ptr(row).cast::<T>() })
+    }
+
+    pub(crate) fn get_mut<T: Any>(&mut self, row: usize) -> Option<&mut T> {
+        assert_eq!(self.type_id, Some(TypeId::of::<T>()));
+        // SAFETY: the type matches and the row is occupied.
+        (row < self.len).then(|| unsafe { &mut *self.ptr(row).cast::<T>() })
+    }
+
+    /// Get a mutable reference through a shared reference to the column and mark the component as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller must hold exclusive access to the column and must not create more than one reference to the same row.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_mut_unchecked<T: Any>(&self, row: usize, tick: u32) -> Option<&mut T> {
+        assert_eq!(self.type_id, Some(TypeId::of::<T>()));
+        Some(unsafe { &mut *self.get_ptr_mut_unchecked(row, tick)?.cast::<T>() })
+    }
+
+    /// Get a pointer to the component in `row`.
+    pub(crate) fn get_ptr(&self, row: usize) -> Option<*mut u8> {
+        (row < self.len).then(|| self.ptr(row))
+    }
+
+    /// Get a pointer for writing to the component in `row` through a shared reference and mark it as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller must hold exclusive access to the column and must not write to the same row from more than one thread.
+    pub(crate) unsafe fn get_ptr_mut_unchecked(&self, row: usize, tick: u32) -> Option<*mut u8> {
+        if row >= self.len {
+            return None;
+        }
+        (*self.ticks[row].get()).changed = tick;
+        Some(self.ptr(row))
+    }
+}
+
+impl Drop for Column {
+    fn drop(&mut self) {
+        if let Some(drop) = self.drop {
+            for row in 0..self.len {
+                // SAFETY: all rows below `len` are occupied.
+                unsafe { drop(self.ptr(row)) };
+            }
+        }
+        if self.item_layout.size() != 0 && self.capacity != 0 {
+            // SAFETY: `data` was allocated with the layout of the current capacity.
+            unsafe {
+                alloc::dealloc(
+                    self.data.as_ptr(),
+                    array_layout(self.item_layout, self.capacity),
+                )
+            };
+        }
+    }
+}
+
+impl std::fmt::Debug for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Column")
+            .field("type_id", &self.type_id)
+            .field("item_layout", &self.item_layout)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+/// The distance between two rows. Layouts of components defined at runtime don't have to be padded to their alignment.
+fn stride(item_layout: Layout) -> usize {
+    item_layout.pad_to_align().size()
+}
+
+fn array_layout(item_layout: Layout, n: usize) -> Layout {
+    stride(item_layout)
+        .checked_mul(n)
+        .and_then(|size| Layout::from_size_align(size, item_layout.align()).ok())
+        .expect("column capacity overflow")
+}
+
+fn dangling(layout: Layout) -> NonNull<u8> {
+    // SAFETY: the alignment is never zero.
+    unsafe { NonNull::new_unchecked(ptr::null_mut::<u8>().wrapping_add(layout.align())) }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        alloc::Layout,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    use super::Column;
+
+    #[test]
+    fn unpadded_layout() {
+        // 3 bytes aligned to 4, so rows have to be 4 bytes apart
+        let layout = Layout::from_size_align(3, 4).unwrap();
+        let mut column = Column::new_dynamic(layout, None);
+        for i in 0..10_u8 {
+            unsafe { column.push_ptr([i, i + 1, i + 2].as_ptr(), 0) };
+        }
+        for i in 0..10_u8 {
+            let ptr = column.get_ptr(i as usize).unwrap();
+            assert_eq!(ptr as usize % 4, 0);
+            assert_eq!(
+                unsafe { std::slice::from_raw_parts(ptr, 3) },
+                &[i, i + 1, i + 2]
+            );
+        }
+    }
+
+    #[test]
+    fn push_and_get() {
+        let mut column = Column::new::<u64>();
+        for value in 0..10 {
+            column.push(value as u64, 0);
+        }
+        *column.get_mut::<u64>(3).unwrap() += 1;
+
+        assert_eq!(column.get::<u64>(3), Some(&4));
+        assert_eq!(column.get::<u64>(9), Some(&9));
+        assert_eq!(column.get::<u64>(10), None);
+    }
+
+    #[test]
+    fn swap_remove() {
+        let mut column = Column::new::<u64>();
+        let mut other = column.empty_clone();
+        for value in 0..4 {
+            column.push(value as u64, value);
+        }
+        column.swap_remove(0);
+        unsafe { column.swap_remove_into(1, &mut other) };
+
+        assert_eq!(column.len(), 2);
+        assert_eq!(column.get::<u64>(0), Some(&3));
+        assert_eq!(column.get::<u64>(1), Some(&2));
+        assert_eq!(other.get::<u64>(0), Some(&1));
+        assert_eq!(column.ticks(0).unwrap().added, 3);
+        assert_eq!(other.ticks(0).unwrap().added, 1);
+    }
+
+    #[test]
+    fn change_ticks() {
+        let mut column = Column::new::<u64>();
+        column.push(1_u64, 1);
+        column.push(2_u64, 1);
+        column.replace(0, 3_u64, 2);
+        unsafe { *column.get_mut_unchecked::<u64>(1, 3).unwrap() += 1 };
+
+        assert_eq!(column.ticks(0).unwrap().added, 1);
+        assert_eq!(column.ticks(0).unwrap().changed, 2);
+        assert_eq!(column.ticks(1).unwrap().changed, 3);
+        assert_eq!(column.get::<u64>(1), Some(&3));
+    }
+
+    #[test]
+    fn drop_components() {
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut column = Column::new::<Counted>();
+        for _ in 0..5 {
+            column.push(Counted(drops.clone()), 0);
+        }
+        column.replace(0, Counted(drops.clone()), 0);
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+        column.swap_remove(1);
+        assert_eq!(drops.load(Ordering::Relaxed), 2);
+        drop(column);
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn panicking_drop() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        struct Panicking(u32);
+        impl Drop for Panicking {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::Relaxed);
+                if self.0 == 0 {
+                    panic!("dropped the first component");
+                }
+            }
+        }
+
+        let mut column = Column::new::<Panicking>();
+        for value in 0..3 {
+            column.push(Panicking(value), value);
+        }
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            column.swap_remove(0);
+        }));
+        assert!(result.is_err());
+
+        assert_eq!(column.len(), 2);
+        assert_eq!(column.get::<Panicking>(0).unwrap().0, 2);
+        assert_eq!(column.get::<Panicking>(1).unwrap().0, 1);
+        assert_eq!(column.ticks(0).unwrap().added, 2);
+        assert_eq!(column.ticks(1).unwrap().added, 1);
+        assert!(column.ticks(2).is_none());
+        drop(column);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn swap_remove_boxed() {
+        let mut column = Column::new::<String>();
+        column.push(String::from("first"), 0);
+        column.push(String::from("second"), 0);
+
+        let value = column.swap_remove_boxed(0);
+        assert_eq!(value.downcast_ref::<String>().unwrap(), "first");
+        assert_eq!(column.get::<String>(0).unwrap(), "second");
+        assert_eq!(column.len(), 1);
+    }
+
+    #[test]
+    fn dynamic_components() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        unsafe fn count_drop(_: *mut u8) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let mut column = Column::new_dynamic(Layout::new::<u32>(), Some(count_drop));
+        let mut other = column.empty_clone();
+        unsafe {
+            column.push_ptr((&1_u32 as *const u32).cast(), 0);
+            column.push_ptr((&2_u32 as *const u32).cast(), 0);
+            column.replace_ptr(0, (&3_u32 as *const u32).cast(), 1);
+            column.swap_remove_into(0, &mut other);
+        }
+        assert_eq!(DROPS.load(Ordering::Relaxed), 1);
+        assert_eq!(unsafe { *column.get_ptr(0).unwrap().cast::<u32>() }, 2);
+        assert_eq!(unsafe { *other.get_ptr(0).unwrap().cast::<u32>() }, 3);
+        assert_eq!(other.ticks(0).unwrap().changed, 1);
+
+        column.swap_remove(0);
+        drop(other);
+        assert_eq!(DROPS.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut column = Column::new::<()>();
+        column.push((), 0);
+        column.push((), 0);
+        column.swap_remove(0);
+        assert!(column.get::<()>(0).is_some());
+        assert!(column.get::<()>(1).is_none());
+    }
+}
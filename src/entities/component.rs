@@ -0,0 +1,77 @@
+use std::{alloc::Layout, borrow::Cow};
+
+use super::StorageType;
+
+/// Identifies a registered component.
+/// Components with a Rust type and components defined at runtime share the same ids.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ComponentId(pub(crate) u32);
+
+impl ComponentId {
+    /// The index of the component. Components are numbered in the order they were registered.
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// Describes a component that is defined at runtime instead of by a Rust type, for example by a script.
+/// ```
+/// use magma_ecs::{entities::component::ComponentDescriptor, World};
+///
+/// let world = World::new();
+/// let position = world.register_dynamic_component(ComponentDescriptor::bytes("Position", 8));
+///
+/// let entity = world.create_entity(()).unwrap();
+/// world
+///     .entity_mut(entity)
+///     .unwrap()
+///     .insert_bytes(position, &[1, 0, 0, 0, 2, 0, 0, 0])
+///     .unwrap();
+///
+/// let position = world.entity(entity).unwrap().get_by_id(position).unwrap();
+/// assert_eq!(unsafe { position.as_bytes() }, &[1, 0, 0, 0, 2, 0, 0, 0]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ComponentDescriptor {
+    pub(crate) name: Cow<'static, str>,
+    pub(crate) layout: Layout,
+    pub(crate) drop: Option<unsafe fn(*mut u8)>,
+    pub(crate) storage: StorageType,
+}
+
+impl ComponentDescriptor {
+    /// Describe a component by its layout and the function dropping it in place.
+    /// The component must be [`Send`] and [`Sync`].
+    pub fn new(
+        name: impl Into<Cow<'static, str>>,
+        layout: Layout,
+        drop: Option<unsafe fn(*mut u8)>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            layout,
+            drop,
+            storage: StorageType::Table,
+        }
+    }
+
+    /// Describe a component that is a blob of `size` bytes without drop glue.
+    /// Its data can be written with [`EntityMut::insert_bytes`](super::entity_ref::EntityMut::insert_bytes).
+    pub fn bytes(name: impl Into<Cow<'static, str>>, size: usize) -> Self {
+        Self::new(name, Layout::array::<u8>(size).unwrap(), None)
+    }
+
+    /// Store the component with the given [`StorageType`] instead of in the archetype tables.
+    pub fn with_storage(mut self, storage: StorageType) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
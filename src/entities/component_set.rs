@@ -1,20 +1,49 @@
-use std::{
-    any::{Any, TypeId},
-    sync::Arc,
-};
+use std::any::{type_name, Any, TypeId};
 
-use parking_lot::RwLock;
+/// Receives the components of a [`ComponentSet`] one by one.
+pub trait ComponentVisitor {
+    fn visit<T: Any + Send + Sync>(&mut self, component: T);
+}
 
+/// A set of components an entity can be created with. This is implemented for tuples of up to 10 components.
+/// Structs can implement it with the `Bundle` derive macro, which requires the `derive` feature.
 pub trait ComponentSet {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, run: R);
+    /// The [`TypeId`]s of the components in the set.
+    fn type_ids() -> Vec<TypeId>;
+
+    /// The type names of the components in the set, in the same order as [`ComponentSet::type_ids`].
+    fn type_names() -> Vec<&'static str>;
+
+    /// Visit the components in the order of [`ComponentSet::type_ids`].
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V);
+}
+
+impl ComponentSet for () {
+    fn type_ids() -> Vec<TypeId> {
+        vec![]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, _: &mut V) {}
 }
 
 impl<C0> ComponentSet for (C0,)
 where
     C0: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![TypeId::of::<C0>()]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![type_name::<C0>()]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
     }
 }
 
@@ -23,9 +52,17 @@ where
     C0: Any + Send + Sync,
     C1: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![TypeId::of::<C0>(), TypeId::of::<C1>()]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![type_name::<C0>(), type_name::<C1>()]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
     }
 }
 
@@ -35,10 +72,18 @@ where
     C1: Any + Send + Sync,
     C2: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![TypeId::of::<C0>(), TypeId::of::<C1>(), TypeId::of::<C2>()]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![type_name::<C0>(), type_name::<C1>(), type_name::<C2>()]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
     }
 }
 
@@ -49,11 +94,29 @@ where
     C2: Any + Send + Sync,
     C3: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
     }
 }
 
@@ -65,12 +128,32 @@ where
     C3: Any + Send + Sync,
     C4: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
     }
 }
 
@@ -83,13 +166,35 @@ where
     C4: Any + Send + Sync,
     C5: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
-        run(self.5.type_id(), Arc::new(RwLock::new(self.5)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+            TypeId::of::<C5>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+            type_name::<C5>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
+        visitor.visit(self.5);
     }
 }
 
@@ -103,14 +208,38 @@ where
     C5: Any + Send + Sync,
     C6: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
-        run(self.5.type_id(), Arc::new(RwLock::new(self.5)));
-        run(self.6.type_id(), Arc::new(RwLock::new(self.6)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+            TypeId::of::<C5>(),
+            TypeId::of::<C6>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+            type_name::<C5>(),
+            type_name::<C6>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
+        visitor.visit(self.5);
+        visitor.visit(self.6);
     }
 }
 
@@ -125,15 +254,41 @@ where
     C6: Any + Send + Sync,
     C7: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
-        run(self.5.type_id(), Arc::new(RwLock::new(self.5)));
-        run(self.6.type_id(), Arc::new(RwLock::new(self.6)));
-        run(self.7.type_id(), Arc::new(RwLock::new(self.7)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+            TypeId::of::<C5>(),
+            TypeId::of::<C6>(),
+            TypeId::of::<C7>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+            type_name::<C5>(),
+            type_name::<C6>(),
+            type_name::<C7>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
+        visitor.visit(self.5);
+        visitor.visit(self.6);
+        visitor.visit(self.7);
     }
 }
 
@@ -149,16 +304,44 @@ where
     C7: Any + Send + Sync,
     C8: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
-        run(self.5.type_id(), Arc::new(RwLock::new(self.5)));
-        run(self.6.type_id(), Arc::new(RwLock::new(self.6)));
-        run(self.7.type_id(), Arc::new(RwLock::new(self.7)));
-        run(self.8.type_id(), Arc::new(RwLock::new(self.8)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+            TypeId::of::<C5>(),
+            TypeId::of::<C6>(),
+            TypeId::of::<C7>(),
+            TypeId::of::<C8>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+            type_name::<C5>(),
+            type_name::<C6>(),
+            type_name::<C7>(),
+            type_name::<C8>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
+        visitor.visit(self.5);
+        visitor.visit(self.6);
+        visitor.visit(self.7);
+        visitor.visit(self.8);
     }
 }
 
@@ -176,16 +359,46 @@ where
     C8: Any + Send + Sync,
     C9: Any + Send + Sync,
 {
-    fn for_components<R: FnMut(TypeId, Arc<RwLock<dyn Any + Send + Sync>>)>(self, mut run: R) {
-        run(self.0.type_id(), Arc::new(RwLock::new(self.0)));
-        run(self.1.type_id(), Arc::new(RwLock::new(self.1)));
-        run(self.2.type_id(), Arc::new(RwLock::new(self.2)));
-        run(self.3.type_id(), Arc::new(RwLock::new(self.3)));
-        run(self.4.type_id(), Arc::new(RwLock::new(self.4)));
-        run(self.5.type_id(), Arc::new(RwLock::new(self.5)));
-        run(self.6.type_id(), Arc::new(RwLock::new(self.6)));
-        run(self.7.type_id(), Arc::new(RwLock::new(self.7)));
-        run(self.8.type_id(), Arc::new(RwLock::new(self.8)));
-        run(self.9.type_id(), Arc::new(RwLock::new(self.9)));
+    fn type_ids() -> Vec<TypeId> {
+        vec![
+            TypeId::of::<C0>(),
+            TypeId::of::<C1>(),
+            TypeId::of::<C2>(),
+            TypeId::of::<C3>(),
+            TypeId::of::<C4>(),
+            TypeId::of::<C5>(),
+            TypeId::of::<C6>(),
+            TypeId::of::<C7>(),
+            TypeId::of::<C8>(),
+            TypeId::of::<C9>(),
+        ]
+    }
+
+    fn type_names() -> Vec<&'static str> {
+        vec![
+            type_name::<C0>(),
+            type_name::<C1>(),
+            type_name::<C2>(),
+            type_name::<C3>(),
+            type_name::<C4>(),
+            type_name::<C5>(),
+            type_name::<C6>(),
+            type_name::<C7>(),
+            type_name::<C8>(),
+            type_name::<C9>(),
+        ]
+    }
+
+    fn for_components<V: ComponentVisitor>(self, visitor: &mut V) {
+        visitor.visit(self.0);
+        visitor.visit(self.1);
+        visitor.visit(self.2);
+        visitor.visit(self.3);
+        visitor.visit(self.4);
+        visitor.visit(self.5);
+        visitor.visit(self.6);
+        visitor.visit(self.7);
+        visitor.visit(self.8);
+        visitor.visit(self.9);
     }
 }
@@ -0,0 +1,46 @@
+use std::fmt::Display;
+
+/// A handle to an entity. It consists of an index into the component storage and a generation.
+/// The generation is increased every time the entity at the index is deleted, so handles to deleted entities can be detected.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Entity {
+    index: u32,
+    generation: u32,
+}
+
+impl Entity {
+    /// Panics if the index doesn't fit into a `u32`, since a truncated index would alias another entity.
+    pub(crate) fn new(index: usize, generation: u32) -> Self {
+        Self {
+            index: u32::try_from(index).expect("too many entities"),
+            generation,
+        }
+    }
+
+    /// The index of the entity in the component storage.
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+
+    /// The generation of the entity.
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+}
+
+impl Display for Entity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}v{}", self.index, self.generation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Entity;
+
+    #[test]
+    #[should_panic(expected = "too many entities")]
+    fn index_overflow() {
+        Entity::new(u32::MAX as usize + 1, 0);
+    }
+}
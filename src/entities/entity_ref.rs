@@ -0,0 +1,303 @@
+use std::{
+    alloc::Layout,
+    any::Any,
+    ops::{Deref, DerefMut},
+};
+
+use parking_lot::{MappedRwLockReadGuard, MappedRwLockWriteGuard};
+
+use crate::{error::EntityError, World};
+
+use super::{column::Column, component::ComponentId, entity::Entity, Entities};
+
+/// Shared access to a single component of an entity. The component's column is locked for reading as long as this exists.
+pub struct ComponentRef<'a, T: Any + Send + Sync> {
+    guard: MappedRwLockReadGuard<'a, T>,
+}
+
+impl<'a, T: Any + Send + Sync> ComponentRef<'a, T> {
+    pub(crate) fn new(guard: MappedRwLockReadGuard<'a, T>) -> Self {
+        Self { guard }
+    }
+}
+
+impl<T: Any + Send + Sync> Deref for ComponentRef<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+/// Exclusive access to a single component of an entity. The component's column is locked for writing as long as this exists.
+pub struct ComponentMut<'a, T: Any + Send + Sync> {
+    guard: MappedRwLockWriteGuard<'a, T>,
+}
+
+impl<'a, T: Any + Send + Sync> ComponentMut<'a, T> {
+    pub(crate) fn new(guard: MappedRwLockWriteGuard<'a, T>) -> Self {
+        Self { guard }
+    }
+}
+
+impl<T: Any + Send + Sync> Deref for ComponentMut<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T: Any + Send + Sync> DerefMut for ComponentMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+/// Shared access to a single component of an entity through a pointer, for components defined at runtime.
+/// The component's column is locked for reading as long as this exists.
+pub struct ComponentPtr<'a> {
+    column: MappedRwLockReadGuard<'a, Column>,
+    row: usize,
+}
+
+impl<'a> ComponentPtr<'a> {
+    pub(crate) fn new(column: MappedRwLockReadGuard<'a, Column>, row: usize) -> Self {
+        Self { column, row }
+    }
+
+    /// The layout of the component.
+    pub fn layout(&self) -> Layout {
+        self.column.item_layout()
+    }
+
+    /// A pointer to the component. It may only be read from while this exists.
+    pub fn as_ptr(&self) -> *const u8 {
+        self.column.get_ptr(self.row).unwrap()
+    }
+
+    /// The bytes of the component.
+    ///
+    /// # Safety
+    /// All bytes of the component have to be initialized.
+    /// This is the case for components written with [`EntityMut::insert_bytes`].
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        std::slice::from_raw_parts(self.as_ptr(), self.layout().size())
+    }
+}
+
+/// Exclusive access to a single component of an entity through a pointer, for components defined at runtime.
+/// The component's column is locked for writing as long as this exists.
+pub struct ComponentPtrMut<'a> {
+    column: MappedRwLockWriteGuard<'a, Column>,
+    row: usize,
+}
+
+impl<'a> ComponentPtrMut<'a> {
+    pub(crate) fn new(column: MappedRwLockWriteGuard<'a, Column>, row: usize) -> Self {
+        Self { column, row }
+    }
+
+    /// The layout of the component.
+    pub fn layout(&self) -> Layout {
+        self.column.item_layout()
+    }
+
+    /// A pointer to the component. It may only be accessed while this exists.
+    pub fn as_mut_ptr(&mut self) -> *mut u8 {
+        self.column.get_ptr(self.row).unwrap()
+    }
+
+    /// The bytes of the component.
+    ///
+    /// # Safety
+    /// All bytes of the component have to be initialized and the written bytes have to form a valid component.
+    pub unsafe fn as_bytes_mut(&mut self) -> &mut [u8] {
+        std::slice::from_raw_parts_mut(self.as_mut_ptr(), self.layout().size())
+    }
+}
+
+/// Read-only access to the components of a single entity.
+#[derive(Debug, Clone, Copy)]
+pub struct EntityRef<'a> {
+    entity: Entity,
+    entities: &'a Entities,
+}
+
+impl<'a> EntityRef<'a> {
+    pub(crate) fn new(entity: Entity, entities: &'a Entities) -> Self {
+        Self { entity, entities }
+    }
+
+    /// The [`Entity`] handle this refers to.
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    /// Get a reference to a component of the entity.
+    pub fn get<T: Any + Send + Sync>(&self) -> Result<ComponentRef<'a, T>, EntityError> {
+        self.entities.component_ref_by_entity_id(self.entity)
+    }
+
+    /// Check if the entity has the specified component.
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.entities.has_component_by_entity_id::<T>(self.entity)
+    }
+
+    /// Get a pointer to a component of the entity by its [`ComponentId`].
+    pub fn get_by_id(&self, id: ComponentId) -> Result<ComponentPtr<'a>, EntityError> {
+        self.entities.component_ptr_by_entity_id(self.entity, id)
+    }
+
+    /// Check if the entity has the component with the [`ComponentId`].
+    pub fn contains_id(&self, id: ComponentId) -> bool {
+        self.entities.has_component_id(self.entity, id)
+    }
+}
+
+/// Read and write access to the components of a single entity.
+#[derive(Debug)]
+pub struct EntityMut<'a> {
+    entity: Entity,
+    world: &'a World,
+}
+
+impl<'a> EntityMut<'a> {
+    pub(crate) fn new(entity: Entity, world: &'a World) -> Self {
+        Self { entity, world }
+    }
+
+    /// The [`Entity`] handle this refers to.
+    pub fn id(&self) -> Entity {
+        self.entity
+    }
+
+    /// Get a reference to a component of the entity.
+    /// It borrows the [`EntityMut`], so it can't be held while the component is accessed mutably through it.
+    pub fn get<T: Any + Send + Sync>(&self) -> Result<ComponentRef<'_, T>, EntityError> {
+        self.world.entities.component_ref_by_entity_id(self.entity)
+    }
+
+    /// Get a mutable reference to a component of the entity.
+    /// It borrows the [`EntityMut`] mutably, so only one can exist at a time.
+    /// ```compile_fail
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// let entity = world.create_entity((1_u32,)).unwrap();
+    /// let mut entity_mut = world.entity_mut(entity).unwrap();
+    /// let first = entity_mut.get_mut::<u32>().unwrap();
+    /// // would deadlock on the column lock
+    /// let second = entity_mut.get_mut::<u32>().unwrap();
+    /// drop(first);
+    /// ```
+    pub fn get_mut<T: Any + Send + Sync>(&mut self) -> Result<ComponentMut<'_, T>, EntityError> {
+        self.world.entities.component_mut_by_entity_id(self.entity)
+    }
+
+    /// Check if the entity has the specified component.
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.world
+            .entities
+            .has_component_by_entity_id::<T>(self.entity)
+    }
+
+    /// Add a component to the entity. An already present component of the same type gets replaced.
+    /// Unregistered components are registered with the default [`StorageType`](super::StorageType).
+    pub fn insert<T: Any + Send + Sync>(&mut self, data: T) -> Result<&mut Self, EntityError> {
+        let entities = &self.world.entities;
+        let bit = entities.register_component::<T>();
+        self.world.insert_hooked(self.entity, bit, || {
+            entities.add_component_by_entity_id(data, self.entity)
+        })?;
+        Ok(self)
+    }
+
+    /// Remove the specified component from the entity.
+    pub fn remove<T: Any + Send + Sync>(&mut self) -> Result<&mut Self, EntityError> {
+        let entities = &self.world.entities;
+        self.world
+            .remove_hooked(self.entity, entities.bit_of::<T>()?, || {
+                entities.remove_component_by_entity_id::<T>(self.entity)
+            })?;
+        Ok(self)
+    }
+
+    /// Remove the specified component from the entity and return it.
+    /// Returns [`None`] if the entity doesn't have the component.
+    pub fn take<T: Any + Send + Sync>(&mut self) -> Result<Option<T>, EntityError> {
+        let entities = &self.world.entities;
+        self.world
+            .remove_hooked(self.entity, entities.bit_of::<T>()?, || {
+                entities.take_component_by_entity_id::<T>(self.entity)
+            })
+    }
+
+    /// Get a pointer to a component of the entity by its [`ComponentId`].
+    pub fn get_by_id(&self, id: ComponentId) -> Result<ComponentPtr<'a>, EntityError> {
+        self.world
+            .entities
+            .component_ptr_by_entity_id(self.entity, id)
+    }
+
+    /// Get a pointer for writing to a component of the entity by its [`ComponentId`]. The component is marked as changed.
+    pub fn get_mut_by_id(&mut self, id: ComponentId) -> Result<ComponentPtrMut<'_>, EntityError> {
+        self.world
+            .entities
+            .component_ptr_mut_by_entity_id(self.entity, id)
+    }
+
+    /// Check if the entity has the component with the [`ComponentId`].
+    pub fn contains_id(&self, id: ComponentId) -> bool {
+        self.world.entities.has_component_id(self.entity, id)
+    }
+
+    /// Add the component `value` points to. An already present component gets replaced.
+    ///
+    /// # Safety
+    /// `value` has to point to a valid component with the layout it was registered with.
+    /// The component is moved into the world, so it must not be used or dropped afterwards.
+    pub unsafe fn insert_by_id(
+        &mut self,
+        id: ComponentId,
+        value: *const u8,
+    ) -> Result<&mut Self, EntityError> {
+        let entities = &self.world.entities;
+        self.world
+            .insert_hooked(self.entity, entities.bit_of_id(id)?, || {
+                entities.add_component_by_id(self.entity, id, value)
+            })?;
+        Ok(self)
+    }
+
+    /// Add a component registered with [`ComponentDescriptor::bytes`](super::component::ComponentDescriptor::bytes).
+    /// An already present component gets replaced.
+    /// Returns an error if the component isn't a byte blob of the same size.
+    pub fn insert_bytes(
+        &mut self,
+        id: ComponentId,
+        bytes: &[u8],
+    ) -> Result<&mut Self, EntityError> {
+        let entities = &self.world.entities;
+        self.world
+            .insert_hooked(self.entity, entities.bit_of_id(id)?, || {
+                entities.add_component_bytes(self.entity, id, bytes)
+            })?;
+        Ok(self)
+    }
+
+    /// Remove the component with the [`ComponentId`] from the entity.
+    pub fn remove_by_id(&mut self, id: ComponentId) -> Result<&mut Self, EntityError> {
+        let entities = &self.world.entities;
+        self.world
+            .remove_hooked(self.entity, entities.bit_of_id(id)?, || {
+                entities.remove_component_by_id(self.entity, id)
+            })?;
+        Ok(self)
+    }
+
+    /// Delete the entity.
+    pub fn despawn(self) -> Result<(), EntityError> {
+        self.world.despawn_hooked(self.entity)
+    }
+}
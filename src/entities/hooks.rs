@@ -0,0 +1,149 @@
+use std::{any::Any, ops::Deref, sync::Arc};
+
+use crate::{error::EntityError, World};
+
+use super::entity::Entity;
+
+/// A type-erased hook. It fetches the component itself, so it can be stored for any component type.
+pub(crate) type ErasedHook = Arc<dyn Fn(&mut DeferredWorld, Entity) + Send + Sync>;
+
+/// The hooks registered for one component type.
+#[derive(Default, Clone)]
+pub(crate) struct ComponentHooks {
+    pub(crate) on_add: Option<ErasedHook>,
+    pub(crate) on_insert: Option<ErasedHook>,
+    pub(crate) on_remove: Option<ErasedHook>,
+}
+
+impl std::fmt::Debug for ComponentHooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ComponentHooks")
+            .field("on_add", &self.on_add.is_some())
+            .field("on_insert", &self.on_insert.is_some())
+            .field("on_remove", &self.on_remove.is_some())
+            .finish()
+    }
+}
+
+/// Wrap a typed hook, so it gets the entity's `T` component. The hook isn't run if the entity doesn't have one.
+pub(crate) fn erase<T: Any + Send + Sync>(
+    hook: impl Fn(&mut DeferredWorld, Entity, &T) + Send + Sync + 'static,
+) -> ErasedHook {
+    Arc::new(move |world, entity| {
+        let entities = &world.world.entities;
+        if let Ok(component) = entities.component_ref_by_entity_id::<T>(entity) {
+            hook(world, entity, &component);
+        }
+    })
+}
+
+/// A structural change queued by a hook.
+type Deferred<'w> = Box<dyn FnOnce(&World) + 'w>;
+
+/// The [`World`] passed to component hooks.
+///
+/// The component passed to a hook stays locked for reading while the hook runs,
+/// so structural changes and writes to components of the same type have to be queued with [`DeferredWorld::defer`].
+/// Queued changes are applied in order after the hook returns.
+pub struct DeferredWorld<'w> {
+    world: &'w World,
+    queue: Vec<Deferred<'w>>,
+}
+
+impl<'w> DeferredWorld<'w> {
+    /// Run `hook` with a [`DeferredWorld`] and apply the queued changes afterwards.
+    pub(crate) fn run(world: &'w World, hook: &ErasedHook, entity: Entity) {
+        let mut deferred = Self {
+            world,
+            queue: vec![],
+        };
+        hook(&mut deferred, entity);
+        for change in deferred.queue {
+            change(world);
+        }
+    }
+
+    /// Queue a change to the [`World`], which is applied after the hook returns.
+    pub fn defer(&mut self, change: impl FnOnce(&World) + 'w) {
+        self.queue.push(Box::new(change));
+    }
+}
+
+impl Deref for DeferredWorld<'_> {
+    type Target = World;
+
+    fn deref(&self) -> &Self::Target {
+        self.world
+    }
+}
+
+/// Selects one of the hooks of a component.
+type HookKind = fn(&ComponentHooks) -> &Option<ErasedHook>;
+
+const ON_ADD: HookKind = |hooks| &hooks.on_add;
+const ON_INSERT: HookKind = |hooks| &hooks.on_insert;
+const ON_REMOVE: HookKind = |hooks| &hooks.on_remove;
+
+impl World {
+    /// Run the selected hook of every component in `bits` for an entity.
+    fn run_hooks(&self, entity: Entity, bits: impl IntoIterator<Item = u32>, kind: HookKind) {
+        for bit in bits {
+            if let Some(hook) = kind(&self.entities.hooks(bit)) {
+                DeferredWorld::run(self, hook, entity);
+            }
+        }
+    }
+
+    /// Run the `on_add` and `on_insert` hooks of all components of a newly spawned entity.
+    pub(crate) fn spawned(&self, entity: Entity) {
+        if !self.entities.has_hooks() {
+            return;
+        }
+        if let Ok(mask) = self.entities.mask(entity) {
+            self.run_hooks(entity, &mask, ON_ADD);
+            self.run_hooks(entity, &mask, ON_INSERT);
+        }
+    }
+
+    /// Add the component with `bit` to an entity with `insert` and run its hooks afterwards.
+    /// `on_add` only runs if the entity didn't have the component before.
+    pub(crate) fn insert_hooked(
+        &self,
+        entity: Entity,
+        bit: u32,
+        insert: impl FnOnce() -> Result<(), EntityError>,
+    ) -> Result<(), EntityError> {
+        if !self.entities.has_hooks() {
+            return insert();
+        }
+        let added = !self.entities.has_component(entity, bit);
+        insert()?;
+        if added {
+            self.run_hooks(entity, [bit], ON_ADD);
+        }
+        self.run_hooks(entity, [bit], ON_INSERT);
+        Ok(())
+    }
+
+    /// Run the `on_remove` hook of the component with `bit` if the entity has it, then remove it with `remove`.
+    pub(crate) fn remove_hooked<R>(
+        &self,
+        entity: Entity,
+        bit: u32,
+        remove: impl FnOnce() -> Result<R, EntityError>,
+    ) -> Result<R, EntityError> {
+        if self.entities.has_hooks() && self.entities.has_component(entity, bit) {
+            self.run_hooks(entity, [bit], ON_REMOVE);
+        }
+        remove()
+    }
+
+    /// Run the `on_remove` hooks of all components of an entity, then delete it.
+    pub(crate) fn despawn_hooked(&self, entity: Entity) -> Result<(), EntityError> {
+        if self.entities.has_hooks() {
+            let mask = self.entities.mask(entity)?;
+            self.run_hooks(entity, &mask, ON_REMOVE);
+        }
+        self.entities.delete_entity_by_id(entity)
+    }
+}
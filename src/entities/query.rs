@@ -1,37 +1,902 @@
-use std::any::{Any, TypeId};
+use std::{
+    any::{type_name, Any, TypeId},
+    collections::BTreeMap,
+    marker::PhantomData,
+};
 
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use roaring::RoaringBitmap;
 
-use crate::error::EntityError;
+use crate::{error::EntityError, systems::access::SystemAccess, World};
 
-use super::{query_entity::QueryEntity, Entities};
+use super::{
+    archetype::Archetype,
+    column::Column,
+    component::ComponentId,
+    entity::Entity,
+    query_entity::QueryEntity,
+    sparse_set::SparseSet,
+    tick::{ComponentTicks, SystemTicks},
+    Entities,
+};
 
-/// Used for querying for entities with specified components
+/// The components a [`Query`] requires and how it accesses them.
+#[derive(Debug, Default, Clone)]
+pub struct Access {
+    with: RoaringBitmap,
+    reads: RoaringBitmap,
+    writes: RoaringBitmap,
+}
+
+fn component_bit<T: Any>(entities: &Entities) -> Result<u32, EntityError> {
+    entities
+        .get_bitmask(&TypeId::of::<T>())
+        .ok_or(EntityError::ComponentNotRegistered(type_name::<T>()))
+}
+
+impl Access {
+    /// Require component `T` and read it.
+    pub fn read<T: Any>(&mut self, entities: &Entities) -> Result<(), EntityError> {
+        self.read_bit(component_bit::<T>(entities)?)
+    }
+
+    /// Require the component with the [`ComponentId`] and read it.
+    pub fn read_id(&mut self, entities: &Entities, id: ComponentId) -> Result<(), EntityError> {
+        self.read_bit(entities.bit_of_id(id)?)
+    }
+
+    fn read_bit(&mut self, bit: u32) -> Result<(), EntityError> {
+        if self.writes.contains(bit) {
+            return Err(EntityError::ConflictingAccess);
+        }
+        self.with.insert(bit);
+        self.reads.insert(bit);
+        Ok(())
+    }
+
+    /// Read and write components like `other`, without requiring them.
+    pub fn optional(&mut self, other: &Access) -> Result<(), EntityError> {
+        if !other.writes.is_disjoint(&self.reads)
+            || !other.writes.is_disjoint(&self.writes)
+            || !other.reads.is_disjoint(&self.writes)
+        {
+            return Err(EntityError::ConflictingAccess);
+        }
+        self.reads |= &other.reads;
+        self.writes |= &other.writes;
+        Ok(())
+    }
+
+    /// Require component `T` and write it.
+    pub fn write<T: Any>(&mut self, entities: &Entities) -> Result<(), EntityError> {
+        self.write_bit(component_bit::<T>(entities)?)
+    }
+
+    /// Require the component with the [`ComponentId`] and write it.
+    pub fn write_id(&mut self, entities: &Entities, id: ComponentId) -> Result<(), EntityError> {
+        self.write_bit(entities.bit_of_id(id)?)
+    }
+
+    fn write_bit(&mut self, bit: u32) -> Result<(), EntityError> {
+        if self.reads.contains(bit) || self.writes.contains(bit) {
+            return Err(EntityError::ConflictingAccess);
+        }
+        self.with.insert(bit);
+        self.writes.insert(bit);
+        Ok(())
+    }
+}
+
+/// The columns of one archetype and the sparse sets used by a [`Query`], locked according to its [`Access`].
+/// Columns and sparse sets are locked in order of their bit masks.
+pub struct Table<'w> {
+    entities: &'w Entities,
+    mask: &'w RoaringBitmap,
+    columns: BTreeMap<u32, Lock<'w, Column>>,
+    sparse_sets: BTreeMap<u32, Lock<'w, SparseSet>>,
+    change_tick: u32,
+}
+
+enum Lock<'w, C> {
+    Read(RwLockReadGuard<'w, C>),
+    Write(RwLockWriteGuard<'w, C>),
+}
+
+impl<'w, C> Lock<'w, C> {
+    /// Lock for writing if `access` writes the component, for reading if it reads it or `filter` contains it.
+    fn new(lock: &'w RwLock<C>, bit: u32, access: &Access, filter: &RoaringBitmap) -> Option<Self> {
+        if access.writes.contains(bit) {
+            Some(Self::Write(lock.write()))
+        } else if access.reads.contains(bit) || filter.contains(bit) {
+            Some(Self::Read(lock.read_recursive()))
+        } else {
+            None
+        }
+    }
+
+    fn get(&self) -> &C {
+        match self {
+            Self::Read(guard) => guard,
+            Self::Write(guard) => guard,
+        }
+    }
+
+    fn is_write(&self) -> bool {
+        matches!(self, Self::Write(_))
+    }
+}
+
+impl<'w> Table<'w> {
+    /// Lock the columns and sparse sets in `access` or `filter`. Changes are marked with `change_tick`.
+    fn lock(
+        entities: &'w Entities,
+        archetype: &'w Archetype,
+        access: &Access,
+        filter: &RoaringBitmap,
+        change_tick: u32,
+    ) -> Self {
+        let columns = archetype
+            .columns
+            .iter()
+            .filter_map(|(bit, column)| Some((*bit, Lock::new(column, *bit, access, filter)?)))
+            .collect();
+        let sparse_sets = entities
+            .sparse_bits()
+            .iter()
+            .filter_map(|bit| {
+                let sparse_set = entities.sparse_set(bit)?;
+                Some((bit, Lock::new(sparse_set, bit, access, filter)?))
+            })
+            .collect();
+        Self {
+            entities,
+            mask: archetype.mask(),
+            columns,
+            sparse_sets,
+            change_tick,
+        }
+    }
+
+    fn storage<T: Any>(&self) -> Option<ComponentStorage<'_>> {
+        let bit = self.entities.get_bitmask(&TypeId::of::<T>())?;
+        if let Some(column) = self.columns.get(&bit) {
+            Some(ComponentStorage::Table(column.get()))
+        } else {
+            Some(ComponentStorage::SparseSet(
+                self.sparse_sets.get(&bit)?.get(),
+            ))
+        }
+    }
+
+    /// The locked column storing a component of the entity in `row`, the entity's row in it and whether it is locked for writing.
+    fn column(&self, bit: u32, row: usize, entity: Entity) -> Option<(&Column, usize, bool)> {
+        if let Some(column) = self.columns.get(&bit) {
+            Some((column.get(), row, column.is_write()))
+        } else {
+            let lock = self.sparse_sets.get(&bit)?;
+            let sparse_set = lock.get();
+            Some((
+                sparse_set.column(),
+                sparse_set.row(entity)?,
+                lock.is_write(),
+            ))
+        }
+    }
+
+    /// The ticks of a component of the entity in `row`.
+    fn ticks(&self, bit: u32, row: usize, entity: Entity) -> Option<ComponentTicks> {
+        let (column, row, _) = self.column(bit, row, entity)?;
+        column.ticks(row)
+    }
+
+    /// Check if the entity in this table has the component with the given bit.
+    fn has(&self, bit: u32, entity: Entity) -> bool {
+        self.mask.contains(bit)
+            || self
+                .sparse_sets
+                .get(&bit)
+                .is_some_and(|sparse_set| sparse_set.get().contains(entity))
+    }
+}
+
+/// A locked table column or sparse set of a component.
+pub enum ComponentStorage<'w> {
+    Table(&'w Column),
+    SparseSet(&'w SparseSet),
+}
+
+impl ComponentStorage<'_> {
+    fn get<T: Any>(&self, row: usize, entity: Entity) -> Option<&T> {
+        match self {
+            Self::Table(column) => column.get(row),
+            Self::SparseSet(sparse_set) => sparse_set.get(entity),
+        }
+    }
+
+    /// # Safety
+    /// The storage has to be locked for writing and every component may only be fetched once.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn get_mut_unchecked<T: Any>(
+        &self,
+        row: usize,
+        entity: Entity,
+        tick: u32,
+    ) -> Option<&mut T> {
+        match self {
+            Self::Table(column) => column.get_mut_unchecked(row, tick),
+            Self::SparseSet(sparse_set) => sparse_set.get_mut_unchecked(entity, tick),
+        }
+    }
+}
+
+/// Data that can be fetched by a typed [`Query`]. This is implemented for `&T`, `&mut T`, [`Entity`], [`Dynamic`], [`Option`] and tuples of those.
+pub trait QueryData {
+    /// The type yielded for every matching entity.
+    type Item<'a>;
+    /// Columns used while an archetype is iterated.
+    type State<'w>: Sync;
+    /// Data borrowed from the state for a single entity.
+    type Guard<'s>;
+
+    /// Register the components required by this fetch.
+    fn init(entities: &Entities, access: &mut Access) -> Result<(), EntityError>;
+
+    /// Declare the components accessed by this fetch, so systems querying it can be scheduled.
+    fn system_access(access: &mut SystemAccess);
+
+    /// Get the state for one archetype. Returns [`None`] if the archetype is missing a required component.
+    fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>>;
+
+    /// Fetch the data of the entity in `row`. Returns [`None`] if the data is missing.
+    fn guard<'s>(state: &'s Self::State<'_>, row: usize, entity: Entity)
+        -> Option<Self::Guard<'s>>;
+
+    /// Get the item from the entity's guard.
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a>;
+}
+
+impl QueryData for () {
+    type Item<'a> = ();
+    type State<'w> = ();
+    type Guard<'s> = ();
+
+    fn init(_: &Entities, _: &mut Access) -> Result<(), EntityError> {
+        Ok(())
+    }
+
+    fn system_access(_: &mut SystemAccess) {}
+
+    fn lock<'w>(_: &'w Table<'_>) -> Option<Self::State<'w>> {
+        Some(())
+    }
+
+    fn guard<'s>(_: &'s Self::State<'_>, _: usize, _: Entity) -> Option<Self::Guard<'s>> {
+        Some(())
+    }
+
+    fn item<'a>(_: &'a mut Self::Guard<'_>) -> Self::Item<'a> {}
+}
+
+impl QueryData for Entity {
+    type Item<'a> = Entity;
+    type State<'w> = ();
+    type Guard<'s> = Entity;
+
+    fn init(_: &Entities, _: &mut Access) -> Result<(), EntityError> {
+        Ok(())
+    }
+
+    fn system_access(_: &mut SystemAccess) {}
+
+    fn lock<'w>(_: &'w Table<'_>) -> Option<Self::State<'w>> {
+        Some(())
+    }
+
+    fn guard<'s>(_: &'s Self::State<'_>, _: usize, entity: Entity) -> Option<Self::Guard<'s>> {
+        Some(entity)
+    }
+
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+        *guard
+    }
+}
+
+impl<T: Any + Send + Sync> QueryData for &T {
+    type Item<'a> = &'a T;
+    type State<'w> = ComponentStorage<'w>;
+    type Guard<'s> = &'s T;
+
+    fn init(entities: &Entities, access: &mut Access) -> Result<(), EntityError> {
+        access.read::<T>(entities)
+    }
+
+    fn system_access(access: &mut SystemAccess) {
+        access.add_read::<T>();
+    }
+
+    fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>> {
+        table.storage::<T>()
+    }
+
+    fn guard<'s>(
+        state: &'s Self::State<'_>,
+        row: usize,
+        entity: Entity,
+    ) -> Option<Self::Guard<'s>> {
+        state.get(row, entity)
+    }
+
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+        guard
+    }
+}
+
+impl<T: Any + Send + Sync> QueryData for &mut T {
+    type Item<'a> = &'a mut T;
+    /// The storage and the tick fetched components are marked as changed with.
+    type State<'w> = (ComponentStorage<'w>, u32);
+    type Guard<'s> = &'s mut T;
+
+    fn init(entities: &Entities, access: &mut Access) -> Result<(), EntityError> {
+        access.write::<T>(entities)
+    }
+
+    fn system_access(access: &mut SystemAccess) {
+        access.add_write::<T>();
+    }
+
+    fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>> {
+        Some((table.storage::<T>()?, table.change_tick))
+    }
+
+    fn guard<'s>(
+        state: &'s Self::State<'_>,
+        row: usize,
+        entity: Entity,
+    ) -> Option<Self::Guard<'s>> {
+        let (storage, tick) = state;
+        // SAFETY: the storage is locked for writing and every entity is only fetched once per iteration.
+        unsafe { storage.get_mut_unchecked(row, entity, *tick) }
+    }
+
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+        guard
+    }
+}
+
+impl<D: QueryData> QueryData for Option<D> {
+    type Item<'a> = Option<D::Item<'a>>;
+    type State<'w> = Option<D::State<'w>>;
+    type Guard<'s> = Option<D::Guard<'s>>;
+
+    fn init(entities: &Entities, access: &mut Access) -> Result<(), EntityError> {
+        let mut inner = Access::default();
+        D::init(entities, &mut inner)?;
+        access.optional(&inner)
+    }
+
+    fn system_access(access: &mut SystemAccess) {
+        D::system_access(access);
+    }
+
+    fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>> {
+        Some(D::lock(table))
+    }
+
+    fn guard<'s>(
+        state: &'s Self::State<'_>,
+        row: usize,
+        entity: Entity,
+    ) -> Option<Self::Guard<'s>> {
+        Some(
+            state
+                .as_ref()
+                .and_then(|state| D::guard(state, row, entity)),
+        )
+    }
+
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+        guard.as_mut().map(D::item)
+    }
+}
+
+/// Fetches pointers to the components a [`Query`] accesses by [`ComponentId`], see [`Query::read_id`] and [`Query::write_id`].
+/// ```
+/// use std::alloc::Layout;
+/// use magma_ecs::{entities::{component::ComponentDescriptor, query::Dynamic}, World};
+///
+/// let world = World::new();
+/// let health = world.register_dynamic_component(ComponentDescriptor::new(
+///     "Health",
+///     Layout::new::<u32>(),
+///     None,
+/// ));
+/// let entity = world.create_entity(()).unwrap();
+/// unsafe {
+///     world
+///         .entity_mut(entity)
+///         .unwrap()
+///         .insert_by_id(health, (&100_u32 as *const u32).cast())
+///         .unwrap();
+/// }
+///
+/// world
+///     .query::<Dynamic>()
+///     .write_id(health)
+///     .unwrap()
+///     .for_each(|components| unsafe { *components.get_mut(health).unwrap().cast::<u32>() -= 10 })
+///     .unwrap();
+///
+/// let health = world.entity(entity).unwrap().get_by_id(health).unwrap();
+/// assert_eq!(unsafe { *health.as_ptr().cast::<u32>() }, 90);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Dynamic;
+
+/// Pointers to the components of one entity, fetched with [`Dynamic`].
+pub struct DynamicComponents<'a> {
+    table: &'a Table<'a>,
+    row: usize,
+    entity: Entity,
+}
+
+impl DynamicComponents<'_> {
+    /// The entity the components belong to.
+    pub fn entity(&self) -> Entity {
+        self.entity
+    }
+
+    /// Get a pointer to a component the query accesses. Returns [`None`] if the query doesn't access it or the entity doesn't have it.
+    pub fn get(&self, id: ComponentId) -> Option<*const u8> {
+        let (column, row, _) = self.table.column(id.0, self.row, self.entity)?;
+        Some(column.get_ptr(row)?.cast_const())
+    }
+
+    /// Get a pointer for writing to a component the query writes and mark the component as changed.
+    /// Returns [`None`] if the query doesn't write it or the entity doesn't have it.
+    pub fn get_mut(&self, id: ComponentId) -> Option<*mut u8> {
+        let (column, row, write) = self.table.column(id.0, self.row, self.entity)?;
+        // SAFETY: the column is locked for writing and the row only belongs to this entity.
+        write.then(|| unsafe { column.get_ptr_mut_unchecked(row, self.table.change_tick) })?
+    }
+}
+
+impl QueryData for Dynamic {
+    type Item<'a> = DynamicComponents<'a>;
+    type State<'w> = &'w Table<'w>;
+    type Guard<'s> = (&'s Table<'s>, usize, Entity);
+
+    fn init(_: &Entities, _: &mut Access) -> Result<(), EntityError> {
+        Ok(())
+    }
+
+    fn system_access(access: &mut SystemAccess) {
+        access.add_all_components();
+    }
+
+    fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>> {
+        Some(table)
+    }
+
+    fn guard<'s>(
+        state: &'s Self::State<'_>,
+        row: usize,
+        entity: Entity,
+    ) -> Option<Self::Guard<'s>> {
+        Some((state, row, entity))
+    }
+
+    fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+        let (table, row, entity) = *guard;
+        DynamicComponents { table, row, entity }
+    }
+}
+
+macro_rules! impl_query_data {
+    ($($name:ident),+) => {
+        #[allow(non_snake_case)]
+        impl<$($name: QueryData),+> QueryData for ($($name,)+) {
+            type Item<'a> = ($($name::Item<'a>,)+);
+            type State<'w> = ($($name::State<'w>,)+);
+            type Guard<'s> = ($($name::Guard<'s>,)+);
+
+            fn init(entities: &Entities, access: &mut Access) -> Result<(), EntityError> {
+                $($name::init(entities, access)?;)+
+                Ok(())
+            }
+
+            fn system_access(access: &mut SystemAccess) {
+                $($name::system_access(access);)+
+            }
+
+            fn lock<'w>(table: &'w Table<'_>) -> Option<Self::State<'w>> {
+                Some(($($name::lock(table)?,)+))
+            }
+
+            fn guard<'s>(
+                state: &'s Self::State<'_>,
+                row: usize,
+                entity: Entity,
+            ) -> Option<Self::Guard<'s>> {
+                let ($($name,)+) = state;
+                Some(($($name::guard($name, row, entity)?,)+))
+            }
+
+            fn item<'a>(guard: &'a mut Self::Guard<'_>) -> Self::Item<'a> {
+                let ($($name,)+) = guard;
+                ($($name::item($name),)+)
+            }
+        }
+    };
+}
+
+impl_query_data!(D0);
+impl_query_data!(D0, D1);
+impl_query_data!(D0, D1, D2);
+impl_query_data!(D0, D1, D2, D3);
+impl_query_data!(D0, D1, D2, D3, D4);
+impl_query_data!(D0, D1, D2, D3, D4, D5);
+impl_query_data!(D0, D1, D2, D3, D4, D5, D6);
+impl_query_data!(D0, D1, D2, D3, D4, D5, D6, D7);
+impl_query_data!(D0, D1, D2, D3, D4, D5, D6, D7, D8);
+impl_query_data!(D0, D1, D2, D3, D4, D5, D6, D7, D8, D9);
+
+/// Used for querying for entities with specified components.
+///
+/// The type parameter is the [`QueryData`] fetched for every matching entity.
+/// An untyped query (`Query<()>`) yields [`QueryEntity`]s through [`Query::run`].
 #[derive(Debug)]
-pub struct Query<'a> {
+pub struct Query<'a, D: QueryData = ()> {
     map: RoaringBitmap,
+    without: RoaringBitmap,
+    or: Vec<RoaringBitmap>,
+    added: RoaringBitmap,
+    changed: RoaringBitmap,
+    world: &'a World,
     entities: &'a Entities,
     type_ids: Vec<TypeId>,
+    access: Access,
+    /// The ticks of the system the query was created in.
+    ticks: SystemTicks,
+    error: Option<EntityError>,
+    _marker: PhantomData<fn() -> D>,
 }
 
-impl<'a> Query<'a> {
-    pub(crate) fn new(entities: &'a Entities) -> Self {
+impl<'a, D: QueryData> Query<'a, D> {
+    pub(crate) fn new(world: &'a World) -> Self {
+        let entities = &world.entities;
+        let mut access = Access::default();
+        let error = D::init(entities, &mut access).err();
         Self {
+            world,
             entities,
-            map: RoaringBitmap::new(),
+            map: access.with.clone(),
+            without: RoaringBitmap::new(),
+            or: vec![],
+            added: RoaringBitmap::new(),
+            changed: RoaringBitmap::new(),
             type_ids: vec![],
+            access,
+            ticks: entities.current_ticks(),
+            error,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Check if the entities of an archetype can match. Sparse set components are checked per entity.
+    fn matches(&self, mask: &RoaringBitmap) -> bool {
+        let sparse = &self.entities.sparse_bits();
+        (&self.map - sparse).is_subset(mask)
+            && self.without.is_disjoint(mask)
+            && self
+                .or
+                .iter()
+                .all(|group| !group.is_disjoint(mask) || !group.is_disjoint(sparse))
+    }
+
+    /// The components that have to be checked per entity: sparse set components and change detection filters.
+    fn entity_filter(&self) -> RoaringBitmap {
+        let mut filter = &self.map | &self.without;
+        for group in &self.or {
+            filter |= group;
+        }
+        (filter & self.entities.sparse_bits()) | &self.added | &self.changed
+    }
+
+    fn matches_entity(&self, table: &Table, row: usize, entity: Entity) -> bool {
+        self.map.iter().all(|bit| table.has(bit, entity))
+            && self.without.iter().all(|bit| !table.has(bit, entity))
+            && self
+                .or
+                .iter()
+                .all(|group| group.iter().any(|bit| table.has(bit, entity)))
+            && self.added.iter().all(|bit| {
+                table
+                    .ticks(bit, row, entity)
+                    .is_some_and(|ticks| ticks.is_added(self.ticks))
+            })
+            && self.changed.iter().all(|bit| {
+                table
+                    .ticks(bit, row, entity)
+                    .is_some_and(|ticks| ticks.is_changed(self.ticks))
+            })
+    }
+
+    fn lock(&self, archetype: &'a Archetype, filter: &RoaringBitmap) -> Table<'a> {
+        Table::lock(
+            self.entities,
+            archetype,
+            &self.access,
+            filter,
+            self.ticks.this_run,
+        )
+    }
+
+    /// The archetypes containing the matching entities.
+    fn archetypes(&self) -> Vec<&'a Archetype> {
+        self.entities
+            .archetypes()
+            .into_iter()
+            .filter(|archetype| self.matches(archetype.mask()))
+            .collect()
+    }
+
+    fn matching(&self) -> Vec<Entity> {
+        let filter = self.entity_filter();
+        self.archetypes()
+            .into_iter()
+            .flat_map(|archetype| {
+                let rows = archetype.entities.read_recursive();
+                if filter.is_empty() {
+                    return rows.clone();
+                }
+                let table = self.lock(archetype, &filter);
+                rows.iter()
+                    .enumerate()
+                    .filter(|(row, entity)| self.matches_entity(&table, *row, **entity))
+                    .map(|(_, entity)| *entity)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Only match entities that have component `T`.
+    pub fn with<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
+        self.map.insert(component_bit::<T>(self.entities)?);
+        Ok(self)
+    }
+
+    /// Only match entities that have the component with the [`ComponentId`].
+    pub fn with_id(&mut self, id: ComponentId) -> Result<&mut Self, EntityError> {
+        self.map.insert(self.entities.bit_of_id(id)?);
+        Ok(self)
+    }
+
+    /// Only match entities that have the component with the [`ComponentId`] and read it. It can be fetched with [`Dynamic`].
+    pub fn read_id(&mut self, id: ComponentId) -> Result<&mut Self, EntityError> {
+        self.access.read_id(self.entities, id)?;
+        self.map.insert(id.0);
+        Ok(self)
+    }
+
+    /// Only match entities that have the component with the [`ComponentId`] and write it. It can be fetched with [`Dynamic`].
+    pub fn write_id(&mut self, id: ComponentId) -> Result<&mut Self, EntityError> {
+        self.access.write_id(self.entities, id)?;
+        self.map.insert(id.0);
+        Ok(self)
+    }
+
+    /// Only match entities whose component `T` was added since the last run of the current system.
+    /// Outside of systems, every component counts as added.
+    /// ```
+    /// use magma_ecs::{systems::Systems, World};
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.add_resource(0_usize).unwrap();
+    /// world.create_entity((20_u32,)).unwrap();
+    ///
+    /// let dispatcher = Systems::new()
+    ///     .with(count_added, "count_added", &[])
+    ///     .build_dispatcher()
+    ///     .unwrap();
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    /// world.resource_ref(|count: &usize| assert_eq!(*count, 1)).unwrap();
+    ///
+    /// world.create_entity((10_u32,)).unwrap();
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    /// // the first entity was already seen in the last run
+    /// world.resource_ref(|count: &usize| assert_eq!(*count, 1)).unwrap();
+    ///
+    /// fn count_added(world: &World) {
+    ///     let mut added = 0;
+    ///     world
+    ///         .query::<&u32>()
+    ///         .added::<u32>()
+    ///         .unwrap()
+    ///         .for_each(|_| added += 1)
+    ///         .unwrap();
+    ///     world.resource_mut(|count: &mut usize| *count = added).unwrap();
+    /// }
+    /// ```
+    pub fn added<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
+        let bit = component_bit::<T>(self.entities)?;
+        self.map.insert(bit);
+        self.added.insert(bit);
+        Ok(self)
+    }
+
+    /// Only match entities whose component `T` was added or mutably accessed since the last run of the current system.
+    /// Fetching `&mut T` in a typed query, [`QueryEntity::component_mut`] and adding a component mark it as changed.
+    /// Outside of systems, every component counts as changed.
+    pub fn changed<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
+        let bit = component_bit::<T>(self.entities)?;
+        self.map.insert(bit);
+        self.changed.insert(bit);
+        Ok(self)
+    }
+
+    /// Only match entities that don't have component `T`.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    /// world.create_entity((20_u32,)).unwrap();
+    /// world.create_entity((10_u32, 1.5_f32)).unwrap();
+    ///
+    /// world
+    ///     .query::<&u32>()
+    ///     .without::<f32>()
+    ///     .unwrap()
+    ///     .for_each(|int| assert_eq!(*int, 20))
+    ///     .unwrap();
+    /// ```
+    pub fn without<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
+        self.without.insert(component_bit::<T>(self.entities)?);
+        Ok(self)
+    }
+
+    /// Only match entities that don't have the component with the [`ComponentId`].
+    pub fn without_id(&mut self, id: ComponentId) -> Result<&mut Self, EntityError> {
+        self.without.insert(self.entities.bit_of_id(id)?);
+        Ok(self)
+    }
+
+    /// Only match entities that have at least one of the components added to the [`Or`] group.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<u64>();
+    /// world.register_component::<f32>();
+    /// world.create_entity((20_u32,)).unwrap();
+    /// world.create_entity((10_u64,)).unwrap();
+    /// world.create_entity((1.5_f32,)).unwrap();
+    ///
+    /// let mut count = 0;
+    /// world
+    ///     .query::<(Option<&u32>, Option<&u64>)>()
+    ///     .or(|group| group.with::<u32>()?.with::<u64>())
+    ///     .unwrap()
+    ///     .for_each(|_| count += 1)
+    ///     .unwrap();
+    /// assert_eq!(count, 2);
+    /// ```
+    pub fn or<R>(&mut self, build: R) -> Result<&mut Self, EntityError>
+    where
+        R: for<'g> FnOnce(&'g mut Or<'a>) -> Result<&'g mut Or<'a>, EntityError>,
+    {
+        let mut group = Or {
+            map: RoaringBitmap::new(),
+            entities: self.entities,
+        };
+        build(&mut group)?;
+        self.or.push(group.map);
+        Ok(self)
+    }
+
+    /// Run a closure on the fetched data of every matching entity.
+    /// Returns an error if the fetched components are not registered or accessed in conflicting ways.
+    /// These errors are only returned here, not when the query is created with [`World::query`].
+    ///
+    /// The archetypes and the accessed components are locked while the closure runs.
+    /// Changing the structure of the world in the closure, e.g. with [`World::create_entity`],
+    /// [`EntityMut::insert`](super::entity_ref::EntityMut::insert) or despawning an entity,
+    /// or running a query with conflicting access deadlocks instead of returning an error.
+    /// Record such changes with [`World::commands`] instead, they are applied after the query.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    /// world.create_entity((20_u32, 1.5_f32)).unwrap();
+    ///
+    /// world
+    ///     .query::<(&mut u32, &f32)>()
+    ///     .for_each(|(int, float)| *int += *float as u32)
+    ///     .unwrap();
+    /// ```
+    pub fn for_each<R: FnMut(D::Item<'_>)>(&self, mut run: R) -> Result<(), EntityError> {
+        if let Some(error) = self.error {
+            return Err(error);
         }
+        let filter = self.entity_filter();
+        for archetype in self.archetypes() {
+            let rows = archetype.entities.read_recursive();
+            let table = self.lock(archetype, &filter);
+            let Some(state) = D::lock(&table) else {
+                continue;
+            };
+            for (row, entity) in rows.iter().enumerate() {
+                if !filter.is_empty() && !self.matches_entity(&table, row, *entity) {
+                    continue;
+                }
+                if let Some(mut guard) = D::guard(&state, row, *entity) {
+                    run(D::item(&mut guard));
+                }
+            }
+        }
+        Ok(())
     }
 
+    /// Like [`Query::for_each`], but runs the closure on the matching entities in parallel.
+    /// The same locks are held while the closure runs, so structural changes have to go through [`World::commands`] as well.
+    pub fn par_for_each<R: Fn(D::Item<'_>) + Send + Sync>(
+        &self,
+        run: R,
+    ) -> Result<(), EntityError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let filter = self.entity_filter();
+        for archetype in self.archetypes() {
+            let rows = archetype.entities.read_recursive();
+            let table = self.lock(archetype, &filter);
+            let Some(state) = D::lock(&table) else {
+                continue;
+            };
+            rows.par_iter().enumerate().for_each(|(row, entity)| {
+                if !filter.is_empty() && !self.matches_entity(&table, row, *entity) {
+                    return;
+                }
+                if let Some(mut guard) = D::guard(&state, row, *entity) {
+                    run(D::item(&mut guard));
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+/// A group of components of which an entity needs to have at least one. See [`Query::or`].
+#[derive(Debug)]
+pub struct Or<'a> {
+    map: RoaringBitmap,
+    entities: &'a Entities,
+}
+
+impl Or<'_> {
+    /// Add component `T` to the group.
+    pub fn with<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
+        self.map.insert(component_bit::<T>(self.entities)?);
+        Ok(self)
+    }
+}
+
+impl<'a> Query<'a> {
     /// Add component to the [`Query`]
     pub fn with_component<T: Any>(&mut self) -> Result<&mut Self, EntityError> {
         let type_id = TypeId::of::<T>();
         if let Some(bit_mask) = self.entities.get_bitmask(&type_id) {
-            self.map.insert(*bit_mask);
+            self.map.insert(bit_mask);
             self.type_ids.push(type_id);
         } else {
-            return Err(EntityError::ComponentNotRegistered);
+            return Err(EntityError::ComponentNotRegistered(type_name::<T>()));
         }
         Ok(self)
     }
@@ -40,7 +905,7 @@ impl<'a> Query<'a> {
     /// ```
     /// use magma_ecs::World;
     ///
-    /// let mut world = World::new();
+    /// let world = World::new();
     /// world.register_component::<u32>();
     /// world.create_entity((20_u32,)).unwrap();
     ///
@@ -53,18 +918,9 @@ impl<'a> Query<'a> {
     /// ```
     pub fn run<R: FnOnce(Vec<QueryEntity>)>(&self, runner: R) {
         let entities = self
-            .entities
-            .map
-            .read()
-            .par_iter()
-            .enumerate()
-            .filter_map(|(index, entity_map)| {
-                if self.map.is_subset(entity_map) {
-                    Some(QueryEntity::new(index, self.entities))
-                } else {
-                    None
-                }
-            })
+            .matching()
+            .into_iter()
+            .map(|entity| QueryEntity::new(entity, self.world))
             .collect();
 
         runner(entities);
@@ -74,13 +930,15 @@ impl<'a> Query<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::entities::StorageType;
 
     #[test]
     fn query_with_component() {
-        let mut entities = Entities::default();
+        let world = World::new();
+        let entities = &world.entities;
         entities.register_component::<u32>();
         entities.register_component::<f32>();
-        let mut query = Query::new(&entities);
+        let mut query = Query::new(&world);
         query
             .with_component::<u32>()
             .unwrap()
@@ -94,7 +952,8 @@ mod test {
 
     #[test]
     fn run_query() {
-        let mut entities = Entities::default();
+        let world = World::new();
+        let entities = &world.entities;
         entities.register_component::<u32>();
         entities.register_component::<f32>();
         entities.create_entity((10_u32, 20.0_f32)).unwrap();
@@ -102,7 +961,7 @@ mod test {
         entities.create_entity((20.0_f32,)).unwrap();
         entities.create_entity((15_u32, 25.0_f32)).unwrap();
 
-        Query::new(&entities)
+        Query::new(&world)
             .with_component::<u32>()
             .unwrap()
             .with_component::<f32>()
@@ -114,4 +973,228 @@ mod test {
                     .unwrap()
             });
     }
+
+    #[test]
+    fn typed_query() {
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+        entities.register_component::<f32>();
+        entities.create_entity((10_u32, 20.0_f32)).unwrap();
+        entities.create_entity((5_u32,)).unwrap();
+        entities.create_entity((15_u32, 25.0_f32)).unwrap();
+
+        Query::<(&mut u32, &f32)>::new(&world)
+            .for_each(|(int, float)| *int += *float as u32)
+            .unwrap();
+
+        let mut sum = 0;
+        Query::<&u32>::new(&world)
+            .for_each(|int| sum += *int)
+            .unwrap();
+        assert_eq!(sum, 30 + 5 + 40);
+    }
+
+    #[test]
+    fn typed_query_validation() {
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+
+        assert_eq!(
+            Query::<(&u32, &f32)>::new(&world).for_each(|_| {}),
+            Err(EntityError::ComponentNotRegistered("f32"))
+        );
+        assert_eq!(
+            Query::<(&u32, &mut u32)>::new(&world).for_each(|_| {}),
+            Err(EntityError::ConflictingAccess)
+        );
+    }
+
+    #[test]
+    fn query_filters() {
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+        entities.register_component::<u64>();
+        entities.register_component::<f32>();
+        entities.create_entity((1_u32, 1.0_f32)).unwrap();
+        entities.create_entity((2_u32,)).unwrap();
+        entities.create_entity((3_u64, 3.0_f32)).unwrap();
+        entities.create_entity((4.0_f32,)).unwrap();
+
+        let mut without = vec![];
+        Query::<&u32>::new(&world)
+            .without::<f32>()
+            .unwrap()
+            .for_each(|int| without.push(*int))
+            .unwrap();
+        assert_eq!(without, vec![2]);
+
+        let mut or = vec![];
+        Query::<(&f32, Option<&u32>)>::new(&world)
+            .or(|group| group.with::<u32>()?.with::<u64>())
+            .unwrap()
+            .for_each(|(float, int)| or.push((*float, int.copied())))
+            .unwrap();
+        assert_eq!(or, vec![(1.0, Some(1)), (3.0, None)]);
+
+        Query::new(&world)
+            .with_component::<f32>()
+            .unwrap()
+            .without::<u64>()
+            .unwrap()
+            .run(|entities| assert_eq!(entities.len(), 2));
+    }
+
+    #[test]
+    fn sparse_set_query() {
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+        entities.register_component_with_storage::<f32>(StorageType::SparseSet);
+        entities.create_entity((1_u32, 1.0_f32)).unwrap();
+        entities.create_entity((2_u32,)).unwrap();
+        entities.create_entity((3.0_f32,)).unwrap();
+
+        let mut fetched = vec![];
+        Query::<(&u32, &mut f32)>::new(&world)
+            .for_each(|(int, float)| {
+                *float += 1.0;
+                fetched.push((*int, *float));
+            })
+            .unwrap();
+        assert_eq!(fetched, vec![(1, 2.0)]);
+
+        let mut without = vec![];
+        Query::<&u32>::new(&world)
+            .without::<f32>()
+            .unwrap()
+            .for_each(|int| without.push(*int))
+            .unwrap();
+        assert_eq!(without, vec![2]);
+
+        let mut optional = vec![];
+        Query::<(&u32, Option<&f32>)>::new(&world)
+            .for_each(|(int, float)| optional.push((*int, float.copied())))
+            .unwrap();
+        assert_eq!(optional, vec![(1, Some(2.0)), (2, None)]);
+
+        Query::new(&world)
+            .with_component::<f32>()
+            .unwrap()
+            .run(|entities| assert_eq!(entities.len(), 2));
+    }
+
+    #[test]
+    fn change_detection() {
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+        entities.register_component_with_storage::<f32>(StorageType::SparseSet);
+        let first = entities.create_entity((1_u32, 1.0_f32)).unwrap();
+        entities.create_entity((2_u32,)).unwrap();
+
+        let count = |query: &mut Query<&u32>| {
+            let mut count = 0;
+            query.for_each(|_| count += 1).unwrap();
+            count
+        };
+
+        let ticks = entities.system_ticks(0);
+        ticks.run(|| {
+            assert_eq!(count(Query::new(&world).added::<u32>().unwrap()), 2);
+            Query::<&mut f32>::new(&world)
+                .for_each(|float| *float += 1.0)
+                .unwrap();
+        });
+
+        entities.create_entity((3_u32,)).unwrap();
+        let ticks = entities.system_ticks(ticks.this_run);
+        ticks.run(|| {
+            assert_eq!(count(Query::new(&world).added::<u32>().unwrap()), 1);
+            assert_eq!(count(Query::new(&world).changed::<f32>().unwrap()), 0);
+        });
+
+        *entities.component_mut_by_entity_id::<u32>(first).unwrap() += 1;
+        entities.add_component_by_entity_id(2.0_f32, first).unwrap();
+        let ticks = entities.system_ticks(ticks.this_run);
+        ticks.run(|| {
+            assert_eq!(count(Query::new(&world).added::<u32>().unwrap()), 0);
+            assert_eq!(count(Query::new(&world).changed::<u32>().unwrap()), 1);
+            assert_eq!(count(Query::new(&world).changed::<f32>().unwrap()), 1);
+            assert_eq!(count(Query::new(&world).added::<f32>().unwrap()), 0);
+        });
+    }
+
+    #[test]
+    fn dynamic_query() {
+        use std::alloc::Layout;
+
+        use crate::entities::component::ComponentDescriptor;
+
+        let world = World::new();
+        let entities = &world.entities;
+        entities.register_component::<u32>();
+        let speed = entities.register_dynamic_component(ComponentDescriptor::new(
+            "Speed",
+            Layout::new::<f32>(),
+            None,
+        ));
+        let frozen = entities.register_dynamic_component(
+            ComponentDescriptor::bytes("Frozen", 0).with_storage(StorageType::SparseSet),
+        );
+        let u32_id = entities.component_id::<u32>().unwrap();
+
+        let first = entities.create_entity((1_u32,)).unwrap();
+        let second = entities.create_entity((2_u32,)).unwrap();
+        entities.create_entity((3_u32,)).unwrap();
+        for entity in [first, second] {
+            unsafe {
+                entities
+                    .add_component_by_id(entity, speed, (&1.5_f32 as *const f32).cast())
+                    .unwrap()
+            };
+        }
+        entities.add_component_bytes(second, frozen, &[]).unwrap();
+
+        let mut moved = vec![];
+        Query::<(&u32, Dynamic)>::new(&world)
+            .write_id(speed)
+            .unwrap()
+            .without_id(frozen)
+            .unwrap()
+            .for_each(|(int, components)| {
+                assert!(components.get(frozen).is_none());
+                let speed = components.get_mut(speed).unwrap().cast::<f32>();
+                unsafe { *speed *= 2.0 };
+                moved.push(*int);
+            })
+            .unwrap();
+        assert_eq!(moved, vec![1]);
+
+        let mut speeds = vec![];
+        Query::<Dynamic>::new(&world)
+            .read_id(speed)
+            .unwrap()
+            .for_each(|components| {
+                assert!(components.get_mut(speed).is_none());
+                speeds.push(unsafe { *components.get(speed).unwrap().cast::<f32>() });
+            })
+            .unwrap();
+        assert_eq!(speeds, vec![3.0, 1.5]);
+
+        let mut count = 0;
+        Query::<()>::new(&world)
+            .with_id(frozen)
+            .unwrap()
+            .for_each(|_| count += 1)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        assert_eq!(
+            Query::<&mut u32>::new(&world).read_id(u32_id).map(|_| ()),
+            Err(EntityError::ConflictingAccess)
+        );
+    }
 }
@@ -1,36 +1,26 @@
-use parking_lot::{RwLock, RwLockReadGuard};
-use std::{
-    any::{Any, TypeId},
-    sync::Arc,
-};
+use std::any::Any;
 
-use crate::error::EntityError;
+use crate::{error::EntityError, World};
 
-use super::Entities;
+use super::entity::Entity;
 
-type ExtractedComponents<'a> =
-    Result<RwLockReadGuard<'a, Vec<Option<Arc<RwLock<dyn Any + Send + Sync>>>>>, EntityError>;
-
-/// A query entity with the entities id and a reference to the [`Entities`] struct.
+/// A query entity with the entities id and a reference to the [`World`].
 #[derive(Debug)]
 pub struct QueryEntity<'a> {
-    pub id: usize,
-    entities: &'a Entities,
+    pub id: Entity,
+    world: &'a World,
 }
 
 impl<'a> QueryEntity<'a> {
-    pub(crate) fn new(id: usize, entities: &'a Entities) -> Self {
-        Self { id, entities }
+    pub(crate) fn new(id: Entity, world: &'a World) -> Self {
+        Self { id, world }
     }
 
-    fn extract_components<T: Any + Send + Sync>(&self) -> ExtractedComponents {
-        let type_id = TypeId::of::<T>();
-        Ok(self
-            .entities
-            .components
-            .get(&type_id)
-            .ok_or(EntityError::ComponentNotInQuery)?
-            .read())
+    fn not_in_query(error: EntityError) -> EntityError {
+        match error {
+            EntityError::ComponentNotRegistered(_) => EntityError::ComponentNotInQuery,
+            error => error,
+        }
     }
 
     /// Operate on reference to component. Returns an error if the component doesn't exist.
@@ -38,12 +28,12 @@ impl<'a> QueryEntity<'a> {
         &self,
         run: R,
     ) -> Result<(), EntityError> {
-        let components = self.extract_components::<T>()?;
-        let borrowed_component = components[self.id]
-            .as_ref()
-            .ok_or(EntityError::ComponentDataDoesNotExist)?
-            .read();
-        run(borrowed_component.downcast_ref::<T>().unwrap());
+        let component = self
+            .world
+            .entities
+            .component_ref_by_entity_id::<T>(self.id)
+            .map_err(Self::not_in_query)?;
+        run(&component);
         Ok(())
     }
 
@@ -52,27 +42,46 @@ impl<'a> QueryEntity<'a> {
         &self,
         run: R,
     ) -> Result<(), EntityError> {
-        let components = self.extract_components::<T>()?;
-        let mut borrowed_component = components[self.id]
-            .as_ref()
-            .ok_or(EntityError::ComponentDataDoesNotExist)?
-            .write();
-        run(borrowed_component.downcast_mut::<T>().unwrap());
+        let mut component = self
+            .world
+            .entities
+            .component_mut_by_entity_id::<T>(self.id)
+            .map_err(Self::not_in_query)?;
+        run(&mut component);
         Ok(())
     }
 
     /// Remove specified component from entity
     pub fn remove_component<T: Any + Send + Sync>(&self) -> Result<(), EntityError> {
-        self.entities.remove_component_by_entity_id::<T>(self.id)
+        let entities = &self.world.entities;
+        self.world
+            .remove_hooked(self.id, entities.bit_of::<T>()?, || {
+                entities.remove_component_by_entity_id::<T>(self.id)
+            })
+    }
+
+    /// Remove specified component from entity and return it. Returns [`None`] if the entity doesn't have the component.
+    pub fn take_component<T: Any + Send + Sync>(&self) -> Option<T> {
+        let entities = &self.world.entities;
+        self.world
+            .remove_hooked(self.id, entities.bit_of::<T>().ok()?, || {
+                entities.take_component_by_entity_id::<T>(self.id)
+            })
+            .ok()
+            .flatten()
     }
 
     /// Add component to entity
-    pub fn add_component(&self, data: impl Any + Send + Sync) -> Result<(), EntityError> {
-        self.entities.add_component_by_entity_id(data, self.id)
+    pub fn add_component<T: Any + Send + Sync>(&self, data: T) -> Result<(), EntityError> {
+        let entities = &self.world.entities;
+        let bit = entities.register_component::<T>();
+        self.world.insert_hooked(self.id, bit, || {
+            entities.add_component_by_entity_id(data, self.id)
+        })
     }
 
     /// Delete this entity
     pub fn delete(self) {
-        self.entities.delete_entity_by_id(self.id).unwrap();
+        self.world.despawn_hooked(self.id).unwrap();
     }
 }
@@ -0,0 +1,119 @@
+use std::{
+    any::{type_name, Any, TypeId},
+    borrow::Cow,
+    collections::HashMap,
+};
+
+use parking_lot::RwLock;
+use roaring::RoaringBitmap;
+
+use super::{
+    column::Column, component::ComponentDescriptor, hooks::ComponentHooks, removed::RemovalLog,
+    sparse_set::SparseSet, StorageType,
+};
+
+/// Everything stored for one registered component type.
+#[derive(Debug)]
+pub(crate) struct ComponentInfo {
+    pub(crate) name: Cow<'static, str>,
+    /// An empty column the columns of new archetypes are cloned from.
+    pub(crate) prototype: Column,
+    /// The storage of the component if it is registered with [`StorageType::SparseSet`].
+    pub(crate) sparse_set: Option<RwLock<SparseSet>>,
+    pub(crate) removed: RwLock<RemovalLog>,
+    pub(crate) hooks: RwLock<ComponentHooks>,
+}
+
+/// All registered components of an [`Entities`](super::Entities) struct. Components are never unregistered.
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    // Boxed for stable addresses, see `Entities::component`.
+    #[allow(clippy::vec_box)]
+    components: Vec<Box<ComponentInfo>>,
+    bit_masks: HashMap<TypeId, u32>,
+    /// The bits of the components defined at runtime by their names.
+    dynamic: HashMap<Cow<'static, str>, u32>,
+    /// The bits of all components stored in sparse sets.
+    sparse: RoaringBitmap,
+}
+
+impl Registry {
+    pub(crate) fn bit(&self, type_id: &TypeId) -> Option<u32> {
+        self.bit_masks.get(type_id).copied()
+    }
+
+    pub(crate) fn dynamic_bit(&self, name: &str) -> Option<u32> {
+        self.dynamic.get(name).copied()
+    }
+
+    pub(crate) fn get(&self, bit: u32) -> Option<&ComponentInfo> {
+        self.components
+            .get(bit as usize)
+            .map(|component| &**component)
+    }
+
+    pub(crate) fn sparse(&self) -> &RoaringBitmap {
+        &self.sparse
+    }
+
+    /// Register a component and return its bit. Returns the existing bit if the component is already registered.
+    pub(crate) fn register<T: Any + Send + Sync>(&mut self, storage: StorageType) -> u32 {
+        let type_id = TypeId::of::<T>();
+        if let Some(bit) = self.bit(&type_id) {
+            return bit;
+        }
+        let bit = self.push(type_name::<T>().into(), Column::new::<T>(), storage);
+        self.bit_masks.insert(type_id, bit);
+        bit
+    }
+
+    /// The bit of the already registered component defined at runtime with the descriptor's name.
+    /// Panics if it was registered with a different layout or without matching drop glue,
+    /// since its data would be read and written with the wrong size.
+    pub(crate) fn existing_dynamic(&self, descriptor: &ComponentDescriptor) -> Option<u32> {
+        let bit = self.dynamic_bit(&descriptor.name)?;
+        let prototype = &self.components[bit as usize].prototype;
+        assert!(
+            prototype.item_layout() == descriptor.layout
+                && prototype.has_drop() == descriptor.drop.is_some(),
+            "component {} is already registered with a different layout or drop function",
+            descriptor.name
+        );
+        Some(bit)
+    }
+
+    /// Register a component defined at runtime and return its bit.
+    /// Returns the existing bit if a component with the same name is already registered.
+    pub(crate) fn register_dynamic(&mut self, descriptor: ComponentDescriptor) -> u32 {
+        if let Some(bit) = self.existing_dynamic(&descriptor) {
+            return bit;
+        }
+        let bit = self.push(
+            descriptor.name.clone(),
+            Column::new_dynamic(descriptor.layout, descriptor.drop),
+            descriptor.storage,
+        );
+        self.dynamic.insert(descriptor.name, bit);
+        bit
+    }
+
+    fn push(&mut self, name: Cow<'static, str>, prototype: Column, storage: StorageType) -> u32 {
+        let bit = self.components.len() as u32;
+        let sparse_set = (storage == StorageType::SparseSet).then(|| {
+            self.sparse.insert(bit);
+            RwLock::new(SparseSet::new(&prototype))
+        });
+        self.components.push(Box::new(ComponentInfo {
+            name,
+            prototype,
+            sparse_set,
+            removed: RwLock::default(),
+            hooks: RwLock::default(),
+        }));
+        bit
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &ComponentInfo> {
+        self.components.iter().map(|component| &**component)
+    }
+}
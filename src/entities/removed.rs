@@ -0,0 +1,87 @@
+use std::{any::Any, marker::PhantomData};
+
+use parking_lot::RwLockReadGuard;
+
+use super::{entity::Entity, tick::SystemTicks};
+
+/// A component removed from an entity.
+#[derive(Debug)]
+pub(crate) struct Removal {
+    entity: Entity,
+    tick: u32,
+    value: Option<Box<dyn Any + Send + Sync>>,
+}
+
+/// The removals of one component type.
+#[derive(Debug, Default)]
+pub(crate) struct RemovalLog {
+    removals: Vec<Removal>,
+    /// Keep the removed values instead of dropping them.
+    pub(crate) keep_values: bool,
+}
+
+impl RemovalLog {
+    pub(crate) fn push(
+        &mut self,
+        entity: Entity,
+        tick: u32,
+        value: Option<Box<dyn Any + Send + Sync>>,
+    ) {
+        self.removals.push(Removal {
+            entity,
+            tick,
+            value,
+        });
+    }
+
+    /// Drop all removals that happened at or before `ticks.last_run`.
+    pub(crate) fn clear_until(&mut self, ticks: SystemTicks) {
+        self.removals.retain(|removal| ticks.is_newer(removal.tick));
+    }
+}
+
+/// The components of type `T` removed since the last run of the current system.
+/// Outside of systems, this contains all removals that haven't been cleared yet.
+/// The removal log is locked for reading as long as this exists.
+pub struct RemovedComponents<'a, T: Any + Send + Sync> {
+    log: RwLockReadGuard<'a, RemovalLog>,
+    ticks: SystemTicks,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'a, T: Any + Send + Sync> RemovedComponents<'a, T> {
+    pub(crate) fn new(log: RwLockReadGuard<'a, RemovalLog>, ticks: SystemTicks) -> Self {
+        Self {
+            log,
+            ticks,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterate over the entities the component was removed from and the removed values.
+    /// The values are only kept for components passed to [`World::track_removed_values`](crate::World::track_removed_values).
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, Option<&T>)> {
+        self.log
+            .removals
+            .iter()
+            .filter(|removal| self.ticks.is_newer(removal.tick))
+            .map(|removal| {
+                (
+                    removal.entity,
+                    removal
+                        .value
+                        .as_ref()
+                        .and_then(|value| value.downcast_ref()),
+                )
+            })
+    }
+
+    /// Iterate over the entities the component was removed from.
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.iter().map(|(entity, _)| entity)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
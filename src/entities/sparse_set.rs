@@ -0,0 +1,125 @@
+use std::any::Any;
+
+use super::{column::Column, entity::Entity};
+
+/// Storage for a component that is stored outside of the archetype tables.
+/// Adding and removing the component doesn't move the entity to another archetype.
+#[derive(Debug)]
+pub struct SparseSet {
+    dense: Column,
+    /// The entity owning each row of `dense`.
+    entities: Vec<Entity>,
+    /// The row of every entity index, if the entity has the component.
+    sparse: Vec<Option<usize>>,
+}
+
+impl SparseSet {
+    /// Create an empty sparse set storing the same component type as `prototype`.
+    pub(crate) fn new(prototype: &Column) -> Self {
+        Self {
+            dense: prototype.empty_clone(),
+            entities: vec![],
+            sparse: vec![],
+        }
+    }
+
+    /// The row of the entity's component in the dense column.
+    pub(crate) fn row(&self, entity: Entity) -> Option<usize> {
+        self.sparse.get(entity.index()).copied().flatten()
+    }
+
+    pub(crate) fn contains(&self, entity: Entity) -> bool {
+        self.row(entity).is_some()
+    }
+
+    pub(crate) fn column(&self) -> &Column {
+        &self.dense
+    }
+
+    pub(crate) fn column_mut(&mut self) -> &mut Column {
+        &mut self.dense
+    }
+
+    /// Add the component to an entity at `tick`, replacing the existing one.
+    pub(crate) fn insert<T: Any>(&mut self, entity: Entity, value: T, tick: u32) {
+        self.insert_with(entity, |column, row| match row {
+            Some(row) => column.replace(row, value, tick),
+            None => column.push(value, tick),
+        });
+    }
+
+    /// Add the component to an entity. `write` gets the row of the existing component or [`None`] if it has to push the component.
+    pub(crate) fn insert_with<R>(
+        &mut self,
+        entity: Entity,
+        write: impl FnOnce(&mut Column, Option<usize>) -> R,
+    ) -> R {
+        if let Some(row) = self.row(entity) {
+            return write(&mut self.dense, Some(row));
+        }
+        if self.sparse.len() <= entity.index() {
+            self.sparse.resize(entity.index() + 1, None);
+        }
+        self.sparse[entity.index()] = Some(self.entities.len());
+        self.entities.push(entity);
+        write(&mut self.dense, None)
+    }
+
+    /// Remove the component of an entity. `remove` has to swap remove the row it gets passed from the column.
+    pub(crate) fn remove_with<R>(
+        &mut self,
+        entity: Entity,
+        remove: impl FnOnce(&mut Column, usize) -> R,
+    ) -> Option<R> {
+        let row = self.row(entity)?;
+        self.sparse[entity.index()] = None;
+        let result = remove(&mut self.dense, row);
+        self.entities.swap_remove(row);
+        if let Some(moved) = self.entities.get(row) {
+            self.sparse[moved.index()] = Some(row);
+        }
+        Some(result)
+    }
+
+    pub(crate) fn get<T: Any>(&self, entity: Entity) -> Option<&T> {
+        self.dense.get(self.row(entity)?)
+    }
+
+    /// Get a mutable reference through a shared reference to the sparse set and mark the component as changed at `tick`.
+    ///
+    /// # Safety
+    /// The caller must hold exclusive access to the sparse set and must not create more than one reference to the same component.
+    #[allow(clippy::mut_from_ref)]
+    pub(crate) unsafe fn get_mut_unchecked<T: Any>(
+        &self,
+        entity: Entity,
+        tick: u32,
+    ) -> Option<&mut T> {
+        self.dense.get_mut_unchecked(self.row(entity)?, tick)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_and_remove() {
+        let mut set = SparseSet::new(&Column::new::<u32>());
+        let first = Entity::new(4, 0);
+        let second = Entity::new(1, 0);
+        set.insert(first, 10_u32, 0);
+        set.insert(second, 20_u32, 0);
+        set.insert(first, 15_u32, 1);
+
+        assert_eq!(set.column().len(), 2);
+        assert_eq!(set.get::<u32>(first), Some(&15));
+        assert_eq!(set.column().ticks(0).unwrap().changed, 1);
+
+        assert!(set.remove_with(first, Column::swap_remove).is_some());
+        assert!(set.remove_with(first, Column::swap_remove).is_none());
+        assert!(!set.contains(first));
+        assert_eq!(set.row(second), Some(0));
+        assert_eq!(set.get::<u32>(second), Some(&20));
+    }
+}
@@ -0,0 +1,105 @@
+use std::cell::Cell;
+
+/// The ticks at which a component was added and last changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ComponentTicks {
+    pub(crate) added: u32,
+    pub(crate) changed: u32,
+}
+
+impl ComponentTicks {
+    pub(crate) fn new(tick: u32) -> Self {
+        Self {
+            added: tick,
+            changed: tick,
+        }
+    }
+
+    pub(crate) fn is_added(&self, ticks: SystemTicks) -> bool {
+        ticks.is_newer(self.added)
+    }
+
+    pub(crate) fn is_changed(&self, ticks: SystemTicks) -> bool {
+        ticks.is_newer(self.changed)
+    }
+}
+
+/// The ticks of a running system.
+/// `last_run` is the tick of the system's previous run, `this_run` is the tick changes made by the system are marked with.
+///
+/// Ticks wrap around, so they are compared by their age relative to `this_run`.
+/// Changes older than `u32::MAX` ticks, for example of a system that didn't run for that long, are not detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SystemTicks {
+    pub(crate) last_run: u32,
+    pub(crate) this_run: u32,
+}
+
+thread_local! {
+    static CURRENT_SYSTEM: Cell<Option<SystemTicks>> = const { Cell::new(None) };
+}
+
+impl SystemTicks {
+    /// The ticks of the system running on this thread.
+    pub(crate) fn current() -> Option<Self> {
+        CURRENT_SYSTEM.with(Cell::get)
+    }
+
+    /// Run a closure as the current system of this thread.
+    /// The previous system is restored afterwards, even if the closure panics.
+    pub(crate) fn run<R>(self, run: impl FnOnce() -> R) -> R {
+        struct Restore(Option<SystemTicks>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                CURRENT_SYSTEM.with(|current| current.set(self.0));
+            }
+        }
+
+        let _restore = Restore(CURRENT_SYSTEM.with(|current| current.replace(Some(self))));
+        run()
+    }
+
+    /// Check if `tick` is after `last_run`.
+    pub(crate) fn is_newer(&self, tick: u32) -> bool {
+        self.this_run.wrapping_sub(tick) < self.this_run.wrapping_sub(self.last_run)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::panic;
+
+    use super::SystemTicks;
+
+    #[test]
+    fn wrapping_ticks() {
+        let ticks = SystemTicks {
+            last_run: 10,
+            this_run: 20,
+        };
+        assert!(ticks.is_newer(11));
+        assert!(ticks.is_newer(20));
+        assert!(!ticks.is_newer(10));
+        assert!(!ticks.is_newer(21));
+
+        let ticks = SystemTicks {
+            last_run: u32::MAX - 5,
+            this_run: 5,
+        };
+        assert!(ticks.is_newer(u32::MAX));
+        assert!(ticks.is_newer(2));
+        assert!(!ticks.is_newer(u32::MAX - 10));
+    }
+
+    #[test]
+    fn restore_after_panic() {
+        let ticks = SystemTicks {
+            last_run: 1,
+            this_run: 2,
+        };
+        let result = panic::catch_unwind(|| ticks.run(|| panic!("system panicked")));
+        assert!(result.is_err());
+        assert_eq!(SystemTicks::current(), None);
+    }
+}
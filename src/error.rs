@@ -1,16 +1,35 @@
 //! Error types
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EntityError {
-    /// attempted to access unregistered component
-    ComponentNotRegistered,
+    /// attempted to access unregistered component, holds the type name of the component
+    ComponentNotRegistered(&'static str),
     /// attempted to access entity that does not exist
     EntityDoesNotExist,
+    /// attempted to access entity through a handle to a deleted entity
+    StaleEntity,
     /// attempted to access component not included in query
     ComponentNotInQuery,
     /// attemted getting component data that does not exist
     ComponentDataDoesNotExist,
     /// attemted downcasting to wrong type
     DowncastToWrongType,
+    /// attempted to access a component mutably while it is already accessed in the same query
+    ConflictingAccess,
+    /// attempted to access a component through a [`ComponentId`](crate::entities::component::ComponentId) that is not registered
+    ComponentIdNotRegistered,
+    /// attempted to write bytes to a component that is not a byte blob of the same size
+    InvalidComponentBytes,
+}
+
+/// Errors when building a [`Dispatcher`](crate::systems::dispatcher::Dispatcher) from [`Systems`](crate::systems::Systems)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// a system or set is ordered relative to a system or set that doesn't exist, holds the name of the ordered system or set and the missing name
+    UnknownDependency(&'static str, &'static str),
+    /// the ordering of systems forms a cycle, holds the names of the systems in the cycle in dependency order
+    DependencyCycle(Vec<&'static str>),
+    /// more than one system was added with the same name, holds the name
+    DuplicateSystem(&'static str),
 }
 
 #[derive(Debug)]
@@ -0,0 +1,197 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    marker::PhantomData,
+    mem,
+};
+
+use parking_lot::{ArcRwLockReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock};
+
+use crate::{entities::tick::SystemTicks, World};
+
+/// An event together with the world tick it was sent at.
+#[derive(Debug)]
+struct EventInstance<T> {
+    event: T,
+    tick: u32,
+}
+
+/// The resource holding the events of type `T`. Add it with [`World::add_event`].
+///
+/// Events are double buffered: the buffers are swapped once per [`Dispatcher::dispatch`](crate::systems::dispatcher::Dispatcher::dispatch),
+/// so an event is kept for two dispatches and every system sees it once.
+#[derive(Debug)]
+pub struct Events<T> {
+    /// The events sent before the last update.
+    previous: Vec<EventInstance<T>>,
+    /// The events sent since the last update.
+    current: Vec<EventInstance<T>>,
+}
+
+impl<T> Default for Events<T> {
+    fn default() -> Self {
+        Self {
+            previous: vec![],
+            current: vec![],
+        }
+    }
+}
+
+impl<T> Events<T> {
+    pub(crate) fn send(&mut self, event: T, tick: u32) {
+        self.current.push(EventInstance { event, tick });
+    }
+
+    /// Swap the buffers, dropping the events sent before the previous update.
+    pub fn update(&mut self) {
+        mem::swap(&mut self.previous, &mut self.current);
+        self.current.clear();
+    }
+
+    /// Iterate over all kept events, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.previous
+            .iter()
+            .chain(&self.current)
+            .map(|instance| &instance.event)
+    }
+
+    /// Iterate over the events sent after `ticks.last_run`.
+    fn iter_since(&self, ticks: SystemTicks) -> impl Iterator<Item = &T> {
+        self.previous
+            .iter()
+            .chain(&self.current)
+            .filter(move |instance| ticks.is_newer(instance.tick))
+            .map(|instance| &instance.event)
+    }
+
+    pub fn len(&self) -> usize {
+        self.previous.len() + self.current.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Reads the events of type `T` sent since the last run of the current system,
+/// so every system has its own cursor into the events.
+/// Outside of systems, all kept events are read.
+/// The events are locked for reading as long as this exists.
+///
+/// Systems reading events should depend on the systems sending them, otherwise they may miss events sent in the same stage.
+pub struct EventReader<T: Any + Send + Sync> {
+    events: ArcRwLockReadGuard<RawRwLock, dyn Any + Send + Sync>,
+    ticks: SystemTicks,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Any + Send + Sync> EventReader<T> {
+    pub(crate) fn new(
+        events: ArcRwLockReadGuard<RawRwLock, dyn Any + Send + Sync>,
+        ticks: SystemTicks,
+    ) -> Self {
+        Self {
+            events,
+            ticks,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Iterate over the unread events, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.events
+            .downcast_ref::<Events<T>>()
+            .unwrap()
+            .iter_since(self.ticks)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+}
+
+/// Sends events of type `T`. The events are locked for writing as long as this exists.
+pub struct EventWriter<'w, T: Any + Send + Sync> {
+    events: ArcRwLockWriteGuard<RawRwLock, dyn Any + Send + Sync>,
+    world: &'w World,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<'w, T: Any + Send + Sync> EventWriter<'w, T> {
+    pub(crate) fn new(
+        events: ArcRwLockWriteGuard<RawRwLock, dyn Any + Send + Sync>,
+        world: &'w World,
+    ) -> Self {
+        Self {
+            events,
+            world,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Send an event.
+    pub fn send(&mut self, event: T) -> &mut Self {
+        let tick = self.world.entities.change_tick();
+        self.events
+            .downcast_mut::<Events<T>>()
+            .unwrap()
+            .send(event, tick);
+        self
+    }
+}
+
+/// Swaps the buffers of the events of one type.
+type UpdateFn = fn(&World);
+
+/// The update functions of all event types added to a [`World`].
+#[derive(Default, Debug)]
+pub(crate) struct EventRegistry(RwLock<HashMap<TypeId, UpdateFn>>);
+
+impl EventRegistry {
+    /// Register the update function of the events of type `T`.
+    pub(crate) fn register<T: Any + Send + Sync>(&self) {
+        self.0.write().insert(TypeId::of::<T>(), update_events::<T>);
+    }
+
+    /// Swap the buffers of all event types.
+    pub(crate) fn update(&self, world: &World) {
+        for update in self.0.read().values() {
+            update(world);
+        }
+    }
+}
+
+fn update_events<T: Any + Send + Sync>(world: &World) {
+    let _ = world.resource_mut(Events::<T>::update);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::entities::tick::SystemTicks;
+
+    use super::Events;
+
+    #[test]
+    fn double_buffer() {
+        let mut events = Events::default();
+        events.send(1, 1);
+        events.update();
+        events.send(2, 2);
+        assert_eq!(events.iter().collect::<Vec<_>>(), vec![&1, &2]);
+        let ticks = SystemTicks {
+            last_run: 1,
+            this_run: 3,
+        };
+        assert_eq!(events.iter_since(ticks).collect::<Vec<_>>(), vec![&2]);
+
+        events.update();
+        assert_eq!(events.iter().collect::<Vec<_>>(), vec![&2]);
+        events.update();
+        assert!(events.is_empty());
+    }
+}
@@ -8,7 +8,7 @@
 //! ```
 //! use magma_ecs::World;
 //!
-//! let mut world = World::new();
+//! let world = World::new();
 //!
 //! // Register a component type.
 //! // This can be any type that implements Any + Send + Sync.
@@ -23,14 +23,33 @@
 
 use std::any::Any;
 
-use entities::{component_set::ComponentSet, query::Query, Entities};
+use commands::{CommandQueue, Commands};
+use entities::{
+    component::{ComponentDescriptor, ComponentId},
+    component_set::ComponentSet,
+    entity::Entity,
+    entity_ref::{EntityMut, EntityRef},
+    hooks::{self, DeferredWorld},
+    query::{Query, QueryData},
+    removed::RemovedComponents,
+    Entities, StorageType,
+};
 use error::{EntityError, ResourceError};
+use events::{EventReader, EventRegistry, EventWriter, Events};
 use resources::Resources;
 
+/// Derive `ComponentSet` for structs. Requires the `derive` feature.
+#[cfg(feature = "derive")]
+pub use magma_ecs_derive::Bundle;
+
+/// Provides [`Commands`] for deferring structural changes.
+pub mod commands;
 /// Provides the [`Entities`] struct as well as [`query`](entities::query) and [`query_entity`](entities::query_entity) modules.
 pub mod entities;
 /// Error types
 pub mod error;
+/// Provides [`Events`] for sending events between systems.
+pub mod events;
 /// Provides the [`Resources`] struct.
 pub mod resources;
 /// Provides the [`Systems`](systems::Systems) struct, from which a [`Dispatcher`](systems::dispatcher::Dispatcher) can be created.
@@ -41,6 +60,8 @@ pub mod systems;
 pub struct World {
     resources: Resources,
     entities: Entities,
+    commands: CommandQueue,
+    events: EventRegistry,
 }
 
 impl World {
@@ -98,49 +119,422 @@ impl World {
         self.resources.resource_mut(run)
     }
 
-    /// Register a component.
-    pub fn register_component<T: Any + Send + Sync>(&mut self) {
+    /// Add the [`Events<T>`] resource, so events of type `T` can be sent and read.
+    /// The events are updated by every [`Dispatcher::dispatch`](systems::dispatcher::Dispatcher::dispatch).
+    /// ```
+    /// use magma_ecs::{systems::Systems, World};
+    ///
+    /// struct Collision(u32);
+    ///
+    /// fn collide(world: &World) {
+    ///     world.send_event(Collision(10)).unwrap();
+    /// }
+    ///
+    /// fn damage(world: &World) {
+    ///     for collision in world.read_events::<Collision>().unwrap().iter() {
+    ///         world.resource_mut(|health: &mut u32| *health -= collision.0).unwrap();
+    ///     }
+    /// }
+    ///
+    /// let world = World::new();
+    /// world.add_event::<Collision>();
+    /// world.add_resource(100_u32).unwrap();
+    ///
+    /// let dispatcher = Systems::new()
+    ///     .with(collide, "collide", &[])
+    ///     .with(damage, "damage", &["collide"])
+    ///     .build_dispatcher()
+    ///     .unwrap();
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    /// world.resource_ref(|health: &u32| assert_eq!(*health, 80)).unwrap();
+    /// ```
+    pub fn add_event<T: Any + Send + Sync>(&self) {
+        let _ = self.resources.add(Events::<T>::default());
+        self.events.register::<T>();
+    }
+
+    /// Send an event of type `T`. Returns an error if the event type wasn't added with [`World::add_event`].
+    pub fn send_event<T: Any + Send + Sync>(&self, event: T) -> Result<(), ResourceError> {
+        self.event_writer()?.send(event);
+        Ok(())
+    }
+
+    /// Get an [`EventWriter`] for sending multiple events of type `T`.
+    /// Returns an error if the event type wasn't added with [`World::add_event`].
+    pub fn event_writer<T: Any + Send + Sync>(&self) -> Result<EventWriter<'_, T>, ResourceError> {
+        Ok(EventWriter::new(
+            self.resources.write_arc::<Events<T>>()?,
+            self,
+        ))
+    }
+
+    /// Read the events of type `T` sent since the last run of the current system.
+    /// Returns an error if the event type wasn't added with [`World::add_event`].
+    pub fn read_events<T: Any + Send + Sync>(&self) -> Result<EventReader<T>, ResourceError> {
+        Ok(EventReader::new(
+            self.resources.read_arc::<Events<T>>()?,
+            self.entities.current_ticks(),
+        ))
+    }
+
+    /// Register a component. The component is stored in the archetype tables.
+    /// This only needs a shared reference, so systems can register components while the world is dispatched.
+    /// Components added to an entity with [`EntityMut::insert`] are registered automatically.
+    /// ```
+    /// use magma_ecs::{systems::Systems, World};
+    ///
+    /// struct Health(u32);
+    ///
+    /// fn spawn_player(world: &World) {
+    ///     world.register_component::<Health>();
+    ///     world.create_entity((Health(100),)).unwrap();
+    /// }
+    ///
+    /// let world = World::new();
+    /// let dispatcher = Systems::new()
+    ///     .with(spawn_player, "spawn_player", &[])
+    ///     .build_dispatcher()
+    ///     .unwrap();
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    ///
+    /// let mut count = 0;
+    /// world.query::<&Health>().for_each(|_| count += 1).unwrap();
+    /// assert_eq!(count, 1);
+    /// ```
+    pub fn register_component<T: Any + Send + Sync>(&self) {
         self.entities.register_component::<T>();
     }
 
+    /// Register a component with the given [`StorageType`].
+    /// Use [`StorageType::SparseSet`] for components that are added and removed frequently.
+    /// Panics if the component is already registered with a different storage type.
+    /// Components are registered with [`StorageType::Table`] when they are first inserted, so register them before that.
+    /// ```
+    /// use magma_ecs::{entities::StorageType, World};
+    ///
+    /// struct Selected;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component_with_storage::<Selected>(StorageType::SparseSet);
+    ///
+    /// let entity = world.create_entity((20_u32,)).unwrap();
+    /// // doesn't move the entity to another archetype
+    /// world.entity_mut(entity).unwrap().insert(Selected).unwrap();
+    ///
+    /// let mut count = 0;
+    /// world
+    ///     .query::<(&u32, &Selected)>()
+    ///     .for_each(|_| count += 1)
+    ///     .unwrap();
+    /// assert_eq!(count, 1);
+    /// ```
+    pub fn register_component_with_storage<T: Any + Send + Sync>(&self, storage: StorageType) {
+        self.entities.register_component_with_storage::<T>(storage);
+    }
+
+    /// Register a component defined at runtime, for example by a script, and return its [`ComponentId`].
+    /// Returns the existing id if a component with the same name is already registered.
+    /// Panics if that component has a different layout or only one of them has a drop function.
+    /// Components defined at runtime are accessed through pointers with [`EntityMut::insert_by_id`], [`EntityRef::get_by_id`]
+    /// and the [`Dynamic`](entities::query::Dynamic) query data.
+    pub fn register_dynamic_component(&self, descriptor: ComponentDescriptor) -> ComponentId {
+        self.entities.register_dynamic_component(descriptor)
+    }
+
+    /// Get the [`ComponentId`] of a registered component.
+    pub fn component_id<T: Any + Send + Sync>(&self) -> Option<ComponentId> {
+        self.entities.component_id::<T>()
+    }
+
+    /// Get the [`ComponentId`] of a component defined at runtime by its name.
+    pub fn component_id_by_name(&self, name: &str) -> Option<ComponentId> {
+        self.entities.component_id_by_name(name)
+    }
+
+    /// Get the name of a registered component. For components with a Rust type, this is its type name.
+    pub fn component_name(&self, id: ComponentId) -> Option<String> {
+        self.entities.component_name(id)
+    }
+
+    /// Register a hook that runs when a `T` component is added to an entity that didn't have one,
+    /// either by spawning the entity or by inserting the component. It runs before the [`World::on_insert`] hook.
+    /// Replaces the previous `on_add` hook of `T`. The component is registered if it isn't yet.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// struct Body(u32);
+    /// struct InPhysicsWorld;
+    ///
+    /// let world = World::new();
+    /// world.on_add::<Body>(|world, entity, body| {
+    ///     assert_eq!(body.0, 10);
+    ///     // the component is still locked, so structural changes are deferred
+    ///     world.defer(move |world| {
+    ///         world.entity_mut(entity).unwrap().insert(InPhysicsWorld).unwrap();
+    ///     });
+    /// });
+    ///
+    /// let entity = world.create_entity((Body(10),)).unwrap();
+    /// assert!(world.entity(entity).unwrap().contains::<InPhysicsWorld>());
+    /// ```
+    pub fn on_add<T: Any + Send + Sync>(
+        &self,
+        hook: impl Fn(&mut DeferredWorld, Entity, &T) + Send + Sync + 'static,
+    ) {
+        let bit = self.entities.register_component::<T>();
+        self.entities
+            .set_hooks(bit, |hooks| hooks.on_add = Some(hooks::erase(hook)));
+    }
+
+    /// Register a hook that runs whenever a `T` component is added to an entity, including when it replaces an existing one.
+    /// Replaces the previous `on_insert` hook of `T`. The component is registered if it isn't yet.
+    pub fn on_insert<T: Any + Send + Sync>(
+        &self,
+        hook: impl Fn(&mut DeferredWorld, Entity, &T) + Send + Sync + 'static,
+    ) {
+        let bit = self.entities.register_component::<T>();
+        self.entities
+            .set_hooks(bit, |hooks| hooks.on_insert = Some(hooks::erase(hook)));
+    }
+
+    /// Register a hook that runs when a `T` component is removed from an entity or the entity is despawned.
+    /// The hook runs before the component is removed, so it can still read it.
+    /// Replaces the previous `on_remove` hook of `T`. The component is registered if it isn't yet.
+    pub fn on_remove<T: Any + Send + Sync>(
+        &self,
+        hook: impl Fn(&mut DeferredWorld, Entity, &T) + Send + Sync + 'static,
+    ) {
+        let bit = self.entities.register_component::<T>();
+        self.entities
+            .set_hooks(bit, |hooks| hooks.on_remove = Some(hooks::erase(hook)));
+    }
+
     /// Spawn an entity with components. Currently the max size for tuples provided to this method is 10.
+    /// Returns an [`Entity`] handle to the spawned entity.
     /// ```
     /// use magma_ecs::World;
     ///
-    /// let mut world = World::new();
+    /// let world = World::new();
     /// world.register_component::<u32>();
     /// world.register_component::<f32>();
     ///
     /// // spawn entity
     /// world.create_entity((30_u32, 60_f32)).unwrap();
     /// // when only adding one component, put a comma after it for rust to recognise it as a tuple
-    /// world.create_entity((20_u32,)).unwrap();
+    /// let entity = world.create_entity((20_u32,)).unwrap();
     /// ```
-    pub fn create_entity(&self, components: impl ComponentSet) -> Result<(), EntityError> {
-        self.entities.create_entity(components)
+    pub fn create_entity(&self, components: impl ComponentSet) -> Result<Entity, EntityError> {
+        let entity = self.entities.create_entity(components)?;
+        self.spawned(entity);
+        Ok(entity)
     }
 
     /// Spawn a batch of entities with the same components. This is more efficient if you have to spawn large amounts of entities.
+    /// Every entity gets its own clone of the components.
+    /// Returns the [`Entity`] handles of the spawned entities.
     /// ```
     /// use magma_ecs::World;
     ///
-    /// let mut world = World::new();
+    /// let world = World::new();
     /// world.register_component::<u32>();
     /// world.register_component::<f32>();
     ///
     /// // spawn 100 entities
-    /// world.create_entity_batch((30_u32, 60_f32), 100).unwrap();
+    /// let entities = world.create_entity_batch((30_u32, 60_f32), 100).unwrap();
+    /// assert_eq!(entities.len(), 100);
     /// ```
     pub fn create_entity_batch(
         &self,
-        components: impl ComponentSet,
+        components: impl ComponentSet + Clone,
         num: usize,
-    ) -> Result<(), EntityError> {
-        self.entities.create_entity_batch(components, num)
+    ) -> Result<Vec<Entity>, EntityError> {
+        let entities = self.entities.create_entity_batch(components, num)?;
+        entities.iter().for_each(|entity| self.spawned(*entity));
+        Ok(entities)
+    }
+
+    /// Spawn an entity for every component set of the iterator. Every entity gets its own components.
+    /// This is more efficient than calling [`World::create_entity`] in a loop.
+    /// Returns the [`Entity`] handles of the spawned entities.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    ///
+    /// let entities = world
+    ///     .spawn_batch((0..100).map(|i| (i as u32, i as f32 * 0.5)))
+    ///     .unwrap();
+    /// assert_eq!(*world.entity(entities[10]).unwrap().get::<u32>().unwrap(), 10);
+    /// ```
+    pub fn spawn_batch<C: ComponentSet>(
+        &self,
+        batch: impl IntoIterator<Item = C>,
+    ) -> Result<Vec<Entity>, EntityError> {
+        let entities = self.entities.spawn_batch(batch)?;
+        entities.iter().for_each(|entity| self.spawned(*entity));
+        Ok(entities)
+    }
+
+    /// Get [`Commands`] to record structural changes, which are applied after the current [`Dispatcher`](systems::dispatcher::Dispatcher) stage
+    /// or with [`World::apply_commands`]. Other systems of the same stage don't see the changes, so their results don't depend on the order the systems run in.
+    /// ```
+    /// use magma_ecs::{systems::Systems, World};
+    ///
+    /// fn spawn_players(world: &World) {
+    ///     let mut commands = world.commands();
+    ///     world.query::<&u32>().for_each(|level| {
+    ///         commands.spawn((*level * 100, 1.0_f32));
+    ///     }).unwrap();
+    /// }
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    /// world.create_entity((1_u32,)).unwrap();
+    ///
+    /// let dispatcher = Systems::new()
+    ///     .with(spawn_players, "spawn_players", &[])
+    ///     .build_dispatcher()
+    ///     .unwrap();
+    /// assert!(dispatcher.dispatch(&world).is_empty());
+    ///
+    /// let mut count = 0;
+    /// world.query::<&f32>().for_each(|_| count += 1).unwrap();
+    /// assert_eq!(count, 1);
+    /// ```
+    pub fn commands(&self) -> Commands<'_> {
+        Commands::new(self)
+    }
+
+    /// Apply the [`Commands`] recorded since they were last applied, in the order they were recorded.
+    /// Returns the errors of the commands that failed, which were skipped.
+    #[must_use = "failed commands are only reported by the returned errors"]
+    pub fn apply_commands(&self) -> Vec<EntityError> {
+        self.commands.apply(self)
+    }
+
+    /// Get read access to a single entity's components.
+    /// Returns an error if the [`Entity`] handle is stale.
+    ///
+    /// Component references lock the component's whole column, which is shared by all entities with the same components.
+    /// Holding a reference while accessing the same component of another entity mutably deadlocks,
+    /// for example through [`World::entity_mut`] or a query.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// let entity = world.create_entity((20_u32,)).unwrap();
+    ///
+    /// let entity_ref = world.entity(entity).unwrap();
+    /// assert_eq!(*entity_ref.get::<u32>().unwrap(), 20);
+    /// ```
+    pub fn entity(&self, entity: Entity) -> Result<EntityRef<'_>, EntityError> {
+        self.entities.entity_ref(entity)
+    }
+
+    /// Get read and write access to a single entity's components.
+    /// Returns an error if the [`Entity`] handle is stale.
+    ///
+    /// Component references lock the component's whole column, which is shared by all entities with the same components.
+    /// Holding a reference while accessing the same component of another entity deadlocks if one of the accesses is mutable.
+    /// The references borrow the [`EntityMut`], so this can't happen with the components of a single entity.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    /// let entity = world.create_entity((20_u32,)).unwrap();
+    ///
+    /// let mut entity_mut = world.entity_mut(entity).unwrap();
+    /// *entity_mut.get_mut::<u32>().unwrap() += 1;
+    /// entity_mut.insert(10.0_f32).unwrap().remove::<u32>().unwrap();
+    /// assert!(!entity_mut.contains::<u32>());
+    /// entity_mut.despawn().unwrap();
+    /// ```
+    pub fn entity_mut(&self, entity: Entity) -> Result<EntityMut<'_>, EntityError> {
+        self.entities.validate(entity)?;
+        Ok(EntityMut::new(entity, self))
+    }
+
+    /// Keep the values of removed `T` components, so they can be read with [`World::removed`].
+    /// Returns an error if the component is not registered.
+    pub fn track_removed_values<T: Any + Send + Sync>(&self) -> Result<(), EntityError> {
+        self.entities.track_removed_values::<T>()
+    }
+
+    /// Get the `T` components removed from entities or despawned since the last run of the current system.
+    /// Removals are kept until the [`Dispatcher`](systems::dispatcher::Dispatcher) has dispatched twice, so every system sees them once.
+    /// Without a dispatcher, they are kept until [`World::clear_removed`] was called twice after them.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.track_removed_values::<u32>().unwrap();
+    ///
+    /// let entity = world.create_entity((20_u32,)).unwrap();
+    /// world.entity_mut(entity).unwrap().despawn().unwrap();
+    ///
+    /// let removed = world.removed::<u32>().unwrap();
+    /// assert_eq!(removed.iter().collect::<Vec<_>>(), vec![(entity, Some(&20))]);
+    /// ```
+    pub fn removed<T: Any + Send + Sync>(&self) -> Result<RemovedComponents<'_, T>, EntityError> {
+        self.entities.removed()
+    }
+
+    /// Drop the removals recorded before the previous call, so every removal is kept until this was called twice after it.
+    /// The [`Dispatcher`](systems::dispatcher::Dispatcher) calls this at the start of every dispatch.
+    /// Worlds used without a dispatcher have to call this regularly, for example once per frame,
+    /// otherwise every removal, including the values kept by [`World::track_removed_values`], stays in memory.
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// let entity = world.create_entity((20_u32,)).unwrap();
+    /// world.entity_mut(entity).unwrap().despawn().unwrap();
+    ///
+    /// world.clear_removed();
+    /// world.clear_removed();
+    /// assert!(!world.removed::<u32>().unwrap().is_empty());
+    /// world.clear_removed();
+    /// assert!(world.removed::<u32>().unwrap().is_empty());
+    /// ```
+    pub fn clear_removed(&self) {
+        self.entities.clear_removed();
     }
 
     /// Get a [`Query`] on the [`World`]'s [`Entities`].
-    pub fn query(&self) -> Query {
-        self.entities.query()
+    /// The type parameter is the [`QueryData`] fetched for every matching entity.
+    /// Leave it out to get an untyped query yielding [`QueryEntity`](entities::query_entity::QueryEntity)s.
+    /// Creating the query doesn't fail: if the fetched components aren't registered,
+    /// the error is returned when the query runs with [`Query::for_each`] or [`Query::par_for_each`].
+    /// ```
+    /// use magma_ecs::World;
+    ///
+    /// let world = World::new();
+    /// world.register_component::<u32>();
+    /// world.register_component::<f32>();
+    /// world.create_entity((20_u32, 1.5_f32)).unwrap();
+    ///
+    /// // typed query
+    /// world
+    ///     .query::<(&u32, &mut f32)>()
+    ///     .for_each(|(int, float)| *float += *int as f32)
+    ///     .unwrap();
+    ///
+    /// // untyped query
+    /// world.query().with_component::<u32>().unwrap().run(|entities| {
+    ///     // do something with the entities
+    /// });
+    /// ```
+    pub fn query<D: QueryData>(&self) -> Query<'_, D> {
+        Query::new(self)
     }
 }
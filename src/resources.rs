@@ -1,4 +1,4 @@
-use parking_lot::RwLock;
+use parking_lot::{ArcRwLockReadGuard, ArcRwLockWriteGuard, RawRwLock, RwLock};
 use std::{
     any::{Any, TypeId},
     collections::HashMap,
@@ -24,6 +24,13 @@ impl Resources {
         }
     }
 
+    /// Add a resource, replacing an already present one of the same type.
+    pub(crate) fn insert(&self, data: impl Any + Send + Sync) {
+        self.data
+            .write()
+            .insert(data.type_id(), Arc::new(RwLock::new(data)));
+    }
+
     pub(crate) fn resource_ref<T: Any + Send + Sync, R: FnOnce(&T)>(
         &self,
         run: R,
@@ -50,6 +57,28 @@ impl Resources {
         }
     }
 
+    /// Lock a resource for reading. The guard doesn't borrow the [`Resources`].
+    pub(crate) fn read_arc<T: Any>(
+        &self,
+    ) -> Result<ArcRwLockReadGuard<RawRwLock, dyn Any + Send + Sync>, ResourceError> {
+        let data = self.data.read();
+        let resource = data
+            .get(&TypeId::of::<T>())
+            .ok_or(ResourceError::ResourceDoesNotExist)?;
+        Ok(resource.read_arc())
+    }
+
+    /// Lock a resource for writing. The guard doesn't borrow the [`Resources`].
+    pub(crate) fn write_arc<T: Any>(
+        &self,
+    ) -> Result<ArcRwLockWriteGuard<RawRwLock, dyn Any + Send + Sync>, ResourceError> {
+        let data = self.data.read();
+        let resource = data
+            .get(&TypeId::of::<T>())
+            .ok_or(ResourceError::ResourceDoesNotExist)?;
+        Ok(resource.write_arc())
+    }
+
     pub(crate) fn remove<T: Any>(&self) {
         let type_id = TypeId::of::<T>();
         self.data.write().remove(&type_id);
@@ -1,68 +1,534 @@
+use std::{any::type_name, marker::PhantomData};
+
+use access::SystemAccess;
 use dispatcher::Dispatcher;
+use param::{SystemParam, SystemParamItem};
 
-use crate::World;
+use crate::{error::ScheduleError, World};
 
+/// Provides [`SystemAccess`](access::SystemAccess), which is used to schedule systems.
+pub mod access;
+/// Provides common run conditions for [`IntoSystem::run_if`] and [`SystemSet::run_if`].
+pub mod condition;
 /// The [`Dispatcher`] is used to dispatch [`Systems`] in parallel on a [`World`].
 pub mod dispatcher;
+/// Provides the [`SystemParam`](param::SystemParam) trait and the [`Res`](param::Res) and [`ResMut`](param::ResMut) parameters.
+pub mod param;
+/// Provides [`Schedules`](schedule::Schedules), which run a dispatcher on startup, before, during and after every frame and at a fixed timestep.
+pub mod schedule;
+
+/// A system converted with [`IntoSystem`].
+pub type BoxedSystem = Box<dyn FnMut(&World) + Send>;
+
+/// A run condition added with [`IntoSystem::run_if`] or [`SystemSet::run_if`].
+pub type BoxedCondition = Box<dyn FnMut(&World) -> bool + Send>;
+
+/// Converts a function or closure into a system.
+/// Implemented for closures taking a `&World` and for functions taking up to 10 [`SystemParam`]s.
+/// Closures can capture configuration and keep state between runs, function systems keep state in [`Local`](param::Local)s.
+/// Adding a function system panics if its parameters conflict with each other, for example [`Res<T>`](param::Res) and [`ResMut<T>`](param::ResMut),
+/// since it would deadlock on their locks.
+/// ```
+/// use magma_ecs::{
+///     entities::query::Query,
+///     systems::{param::Res, Systems},
+///     World,
+/// };
+///
+/// struct Position(f32);
+/// struct Velocity(f32);
+/// struct DeltaTime(f32);
+///
+/// fn movement(query: Query<(&mut Position, &Velocity)>, delta: Res<DeltaTime>) {
+///     query
+///         .for_each(|(position, velocity)| position.0 += velocity.0 * delta.0)
+///         .unwrap();
+/// }
+///
+/// let world = World::new();
+/// world.register_component::<Position>();
+/// world.register_component::<Velocity>();
+/// world.add_resource(DeltaTime(0.5)).unwrap();
+/// let entity = world.create_entity((Position(0.0), Velocity(2.0))).unwrap();
+///
+/// let dispatcher = Systems::new()
+///     .with(movement, "movement", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// assert!(dispatcher.dispatch(&world).is_empty());
+/// assert_eq!(world.entity(entity).unwrap().get::<Position>().unwrap().0, 1.0);
+/// ```
+pub trait IntoSystem<Marker> {
+    /// The components and resources the system accesses.
+    fn access(&self) -> SystemAccess;
+
+    fn into_system(self) -> BoxedSystem;
+
+    /// The ordering declared with [`before`](IntoSystem::before), [`after`](IntoSystem::after) and [`in_set`](IntoSystem::in_set).
+    fn order(&self) -> SystemOrder {
+        SystemOrder::default()
+    }
+
+    /// Take the run conditions added with [`run_if`](IntoSystem::run_if).
+    fn conditions(&mut self) -> Vec<BoxedCondition> {
+        vec![]
+    }
+
+    /// Run the system before the system or all systems of the [`SystemSet`] with this name.
+    fn before(self, name: &'static str) -> Configured<Self, Marker>
+    where
+        Self: Sized,
+    {
+        Configured::new(self).before(name)
+    }
+
+    /// Run the system after the system or all systems of the [`SystemSet`] with this name.
+    fn after(self, name: &'static str) -> Configured<Self, Marker>
+    where
+        Self: Sized,
+    {
+        Configured::new(self).after(name)
+    }
+
+    /// Add the system to the [`SystemSet`] with this name.
+    fn in_set(self, set: &'static str) -> Configured<Self, Marker>
+    where
+        Self: Sized,
+    {
+        Configured::new(self).in_set(set)
+    }
+
+    /// Only run the system if the condition returns `true`.
+    /// Conditions are evaluated by [`Dispatcher::dispatch`](dispatcher::Dispatcher::dispatch) before the stage of the system runs.
+    /// See [`condition`] for common conditions.
+    fn run_if(
+        self,
+        condition: impl FnMut(&World) -> bool + Send + 'static,
+    ) -> Configured<Self, Marker>
+    where
+        Self: Sized,
+    {
+        Configured::new(self).run_if(condition)
+    }
+}
+
+/// Marks systems taking a `&World`.
+pub struct IsWorldSystem;
+
+/// Marks systems taking [`SystemParam`]s.
+pub struct IsFunctionSystem;
+
+/// Marks systems with a declared [`SystemOrder`] or run conditions.
+pub struct IsConfigured;
+
+/// Where a system runs relative to other systems and [`SystemSet`]s, referred to by their names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemOrder {
+    pub(crate) before: Vec<&'static str>,
+    pub(crate) after: Vec<&'static str>,
+    pub(crate) sets: Vec<&'static str>,
+}
+
+/// A system with a declared [`SystemOrder`] or run conditions.
+/// Created with [`IntoSystem::before`], [`IntoSystem::after`], [`IntoSystem::in_set`] and [`IntoSystem::run_if`].
+/// ```
+/// use magma_ecs::{
+///     systems::{IntoSystem, Systems},
+///     World,
+/// };
+///
+/// fn render_prep(_: &World) {}
+/// fn plugin_system(_: &World) {}
+///
+/// let dispatcher = Systems::new()
+///     .with(render_prep, "render_prep", &[])
+///     .with(plugin_system.before("render_prep"), "plugin_system", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// ```
+pub struct Configured<S, M> {
+    system: S,
+    order: SystemOrder,
+    conditions: Vec<BoxedCondition>,
+    _marker: PhantomData<fn() -> M>,
+}
+
+impl<M, S: IntoSystem<M>> Configured<S, M> {
+    fn new(mut system: S) -> Self {
+        Self {
+            order: system.order(),
+            conditions: system.conditions(),
+            system,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Run the system before the system or all systems of the [`SystemSet`] with this name.
+    pub fn before(mut self, name: &'static str) -> Self {
+        self.order.before.push(name);
+        self
+    }
+
+    /// Run the system after the system or all systems of the [`SystemSet`] with this name.
+    pub fn after(mut self, name: &'static str) -> Self {
+        self.order.after.push(name);
+        self
+    }
+
+    /// Add the system to the [`SystemSet`] with this name.
+    pub fn in_set(mut self, set: &'static str) -> Self {
+        self.order.sets.push(set);
+        self
+    }
+
+    /// Only run the system if the condition returns `true`.
+    pub fn run_if(mut self, condition: impl FnMut(&World) -> bool + Send + 'static) -> Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+}
+
+impl<M, S: IntoSystem<M>> IntoSystem<(IsConfigured, M)> for Configured<S, M> {
+    fn access(&self) -> SystemAccess {
+        self.system.access()
+    }
+
+    fn into_system(self) -> BoxedSystem {
+        self.system.into_system()
+    }
+
+    fn order(&self) -> SystemOrder {
+        self.order.clone()
+    }
+
+    fn conditions(&mut self) -> Vec<BoxedCondition> {
+        std::mem::take(&mut self.conditions)
+    }
+}
+
+impl<F: FnMut(&World) + Send + 'static> IntoSystem<IsWorldSystem> for F {
+    /// Systems taking a `&World` may access anything, so they don't run in parallel with systems declaring their access.
+    /// They run in parallel with each other, as their locks are only held while they access the world.
+    fn access(&self) -> SystemAccess {
+        SystemAccess::undeclared()
+    }
+
+    fn into_system(self) -> BoxedSystem {
+        Box::new(self)
+    }
+}
+
+/// A system taking a `&World` with declared access. Created with [`with_access`].
+pub struct WithAccess<F> {
+    run: F,
+    access: SystemAccess,
+}
+
+/// Declare the access of a system taking a `&World`, so it can run in parallel with systems it doesn't conflict with.
+/// The system must not access anything else.
+/// ```
+/// use magma_ecs::{
+///     systems::{access::SystemAccess, with_access, Systems},
+///     World,
+/// };
+///
+/// fn heal(world: &World) {
+///     world.query::<&mut u32>().for_each(|health| *health += 1).unwrap();
+/// }
+///
+/// let dispatcher = Systems::new()
+///     .with(with_access(heal, SystemAccess::new().write::<u32>()), "heal", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// ```
+pub fn with_access<F: FnMut(&World) + Send + 'static>(
+    run: F,
+    access: SystemAccess,
+) -> WithAccess<F> {
+    WithAccess { run, access }
+}
+
+impl<F: FnMut(&World) + Send + 'static> IntoSystem<IsWorldSystem> for WithAccess<F> {
+    fn access(&self) -> SystemAccess {
+        self.access.clone()
+    }
+
+    fn into_system(self) -> BoxedSystem {
+        Box::new(self.run)
+    }
+}
+
+macro_rules! impl_into_system {
+    ($($name:ident),*) => {
+        #[allow(non_snake_case)]
+        impl<F, $($name: SystemParam),*> IntoSystem<(IsFunctionSystem, fn($($name,)*))> for F
+        where
+            F: FnMut($($name),*) + FnMut($(SystemParamItem<$name>),*) + Send + 'static,
+        {
+            /// Panics if parameters conflict with each other, since the system would deadlock on their locks.
+            #[allow(unused_mut)]
+            fn access(&self) -> SystemAccess {
+                let mut access = SystemAccess::new();
+                $(
+                    let mut param = SystemAccess::new();
+                    $name::access(&mut param);
+                    assert!(
+                        !access.conflicts_with(&param),
+                        "parameter {} of system {} conflicts with a previous parameter",
+                        type_name::<$name>(),
+                        type_name::<F>(),
+                    );
+                    access.extend(&param);
+                )*
+                access
+            }
+
+            fn into_system(mut self) -> BoxedSystem {
+                // Calling through a generic function selects the `FnMut` impl taking the fetched items.
+                #[allow(clippy::too_many_arguments)]
+                fn call<$($name),*>(run: &mut impl FnMut($($name),*), $($name: $name),*) {
+                    run($($name),*);
+                }
+
+                let mut state = ($($name::init_state(),)*);
+                #[allow(unused_variables)]
+                Box::new(move |world| {
+                    let ($($name,)*) = &mut state;
+                    $(let $name = $name::fetch($name, world);)*
+                    call(&mut self, $($name),*);
+                })
+            }
+        }
+    };
+}
+
+impl_into_system!();
+impl_into_system!(P0);
+impl_into_system!(P0, P1);
+impl_into_system!(P0, P1, P2);
+impl_into_system!(P0, P1, P2, P3);
+impl_into_system!(P0, P1, P2, P3, P4);
+impl_into_system!(P0, P1, P2, P3, P4, P5);
+impl_into_system!(P0, P1, P2, P3, P4, P5, P6);
+impl_into_system!(P0, P1, P2, P3, P4, P5, P6, P7);
+impl_into_system!(P0, P1, P2, P3, P4, P5, P6, P7, P8);
+impl_into_system!(P0, P1, P2, P3, P4, P5, P6, P7, P8, P9);
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub(crate) struct System {
-    pub run: fn(&World),
+    pub run: BoxedSystem,
     pub name: &'static str,
-    pub deps: &'static [&'static str],
+    pub access: SystemAccess,
+    /// The declared order, with the dependencies added to [`SystemOrder::after`].
+    pub order: SystemOrder,
+    pub conditions: Vec<BoxedCondition>,
 }
 
 impl System {
-    fn new(run: fn(&World), name: &'static str, deps: &'static [&'static str]) -> Self {
-        Self { run, name, deps }
+    fn new<M>(
+        mut run: impl IntoSystem<M>,
+        name: &'static str,
+        deps: &'static [&'static str],
+    ) -> Self {
+        let mut order = run.order();
+        order.after.extend(deps);
+        Self {
+            access: run.access(),
+            conditions: run.conditions(),
+            run: run.into_system(),
+            name,
+            order,
+        }
+    }
+}
+
+impl std::fmt::Debug for System {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("System")
+            .field("name", &self.name)
+            .field("access", &self.access)
+            .field("order", &self.order)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A named group of systems, which can be ordered as a whole. Systems are added to it with [`IntoSystem::in_set`].
+/// Ordering a system or set before or after a set orders it relative to all systems in the set.
+/// ```
+/// use magma_ecs::{
+///     systems::{IntoSystem, SystemSet, Systems},
+///     World,
+/// };
+///
+/// fn read_input(_: &World) {}
+/// fn movement(_: &World) {}
+/// fn draw(_: &World) {}
+///
+/// let dispatcher = Systems::new()
+///     .with_set(SystemSet::new("Input").before("Physics"))
+///     .with_set(SystemSet::new("Physics").before("Render"))
+///     .with(draw.in_set("Render"), "draw", &[])
+///     .with(movement.in_set("Physics"), "movement", &[])
+///     .with(read_input.in_set("Input"), "read_input", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// ```
+pub struct SystemSet {
+    pub(crate) name: &'static str,
+    pub(crate) before: Vec<&'static str>,
+    pub(crate) after: Vec<&'static str>,
+    pub(crate) conditions: Vec<BoxedCondition>,
+}
+
+impl SystemSet {
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            before: vec![],
+            after: vec![],
+            conditions: vec![],
+        }
+    }
+
+    /// Only run the systems of the set if the condition returns `true`.
+    /// The condition is evaluated once per [`Dispatcher::dispatch`](dispatcher::Dispatcher::dispatch), before the first stage with a system of the set runs.
+    pub fn run_if(mut self, condition: impl FnMut(&World) -> bool + Send + 'static) -> Self {
+        self.conditions.push(Box::new(condition));
+        self
+    }
+
+    /// Run the systems of the set before the system or all systems of the set with this name.
+    pub fn before(mut self, name: &'static str) -> Self {
+        self.before.push(name);
+        self
+    }
+
+    /// Run the systems of the set after the system or all systems of the set with this name.
+    pub fn after(mut self, name: &'static str) -> Self {
+        self.after.push(name);
+        self
     }
 }
 
-/// Holds systems and their dependencies
-#[derive(Default, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Systems(pub(crate) Vec<System>);
+impl std::fmt::Debug for SystemSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SystemSet")
+            .field("name", &self.name)
+            .field("before", &self.before)
+            .field("after", &self.after)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Holds systems, their dependencies and the configuration of [`SystemSet`]s
+#[derive(Default, Debug)]
+pub struct Systems {
+    pub(crate) systems: Vec<System>,
+    pub(crate) sets: Vec<SystemSet>,
+}
+
+// Systems are compared by their names and ordering and sets by their configuration,
+// since the systems themselves are closures and function pointer comparisons aren't meaningful.
+type SystemsKey<'a> = (
+    Vec<(&'static str, &'a SystemOrder)>,
+    Vec<(&'static str, &'a [&'static str], &'a [&'static str])>,
+);
 
 impl Systems {
     pub fn new() -> Self {
-        Self(vec![])
+        Self::default()
+    }
+
+    fn key(&self) -> SystemsKey<'_> {
+        (
+            self.systems
+                .iter()
+                .map(|system| (system.name, &system.order))
+                .collect(),
+            self.sets
+                .iter()
+                .map(|set| (set.name, &set.before[..], &set.after[..]))
+                .collect(),
+        )
     }
 
     /// Add a system on creation.
-    /// **run:** the system's function,
+    /// **run:** the system's function or closure, either taking a `&World` or [`SystemParam`]s,
     /// **name:** the system's name,
     /// **deps:** the system's dependencies,
-    pub fn with(
+    pub fn with<M>(
         mut self,
-        run: fn(&World),
+        run: impl IntoSystem<M>,
         name: &'static str,
         deps: &'static [&'static str],
     ) -> Self {
-        self.0.push(System::new(run, name, deps));
+        self.systems.push(System::new(run, name, deps));
         self
     }
 
     /// Add a system
-    pub fn add(
+    pub fn add<M>(
         &mut self,
-        run: fn(&World),
+        run: impl IntoSystem<M>,
         name: &'static str,
         deps: &'static [&'static str],
     ) -> &mut Self {
-        self.0.push(System::new(run, name, deps));
+        self.systems.push(System::new(run, name, deps));
+        self
+    }
+
+    /// Configure a [`SystemSet`] on creation. A set may be configured more than once.
+    pub fn with_set(mut self, set: SystemSet) -> Self {
+        self.sets.push(set);
+        self
+    }
+
+    /// Configure a [`SystemSet`]. A set may be configured more than once.
+    pub fn add_set(&mut self, set: SystemSet) -> &mut Self {
+        self.sets.push(set);
         self
     }
 
     /// Build a [`Dispatcher`] from the [`Systems`] to be run on the [`World`].
-    pub fn build_dispatcher(self) -> Dispatcher {
+    /// Returns an error if system names aren't unique, a system or set is ordered relative to a name that doesn't exist or the ordering forms a cycle.
+    pub fn build_dispatcher(self) -> Result<Dispatcher, ScheduleError> {
         Dispatcher::from_systems(self)
     }
 }
 
+impl PartialEq for Systems {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Systems {}
+
+impl PartialOrd for Systems {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Systems {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl std::hash::Hash for Systems {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::World;
+    use crate::{error::ScheduleError, World};
 
-    use super::Systems;
+    use super::{
+        param::{Res, ResMut},
+        IntoSystem, SystemSet, Systems,
+    };
 
     #[test]
     fn create_systems() {
@@ -71,7 +537,7 @@ mod tests {
             "system_2",
             &["system_1"],
         );
-        assert_eq!(systems.0[1].name, "system_2");
+        assert_eq!(systems.systems[1].name, "system_2");
     }
 
     #[test]
@@ -89,7 +555,101 @@ mod tests {
             "system_2",
             &["system_1"],
         );
-        systems.build_dispatcher();
+        systems.build_dispatcher().unwrap();
+    }
+
+    #[test]
+    fn invalid_dependencies() {
+        assert_eq!(
+            Systems::new()
+                .with(system_1, "system_1", &[])
+                .with(system_2, "system_1", &[])
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::DuplicateSystem("system_1")
+        );
+        assert_eq!(
+            Systems::new()
+                .with(system_1, "system_1", &["sytem_2"])
+                .with(system_2, "system_2", &[])
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::UnknownDependency("system_1", "sytem_2")
+        );
+        assert_eq!(
+            Systems::new()
+                .with(system_1, "system_1", &[])
+                .with(system_2, "system_2", &["system_1", "system_4"])
+                .with(system_1, "system_3", &["system_2"])
+                .with(system_2, "system_4", &["system_3"])
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::DependencyCycle(vec!["system_2", "system_4", "system_3"])
+        );
+        assert_eq!(
+            Systems::new()
+                .with(system_1, "system_1", &["system_1"])
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::DependencyCycle(vec!["system_1"])
+        );
+    }
+
+    #[test]
+    fn invalid_ordering() {
+        assert_eq!(
+            Systems::new()
+                .with(system_1.before("render"), "system_1", &[])
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::UnknownDependency("system_1", "render")
+        );
+        assert_eq!(
+            Systems::new()
+                .with_set(SystemSet::new("physics").after("input"))
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::UnknownDependency("physics", "input")
+        );
+        assert_eq!(
+            Systems::new()
+                .with_set(SystemSet::new("input").before("physics"))
+                .with(system_1.in_set("input"), "system_1", &[])
+                .with(
+                    system_2.in_set("physics").before("system_1"),
+                    "system_2",
+                    &[]
+                )
+                .build_dispatcher()
+                .unwrap_err(),
+            ScheduleError::DependencyCycle(vec!["system_1", "system_2"])
+        );
+    }
+
+    #[test]
+    fn compare_systems() {
+        let systems = || Systems::new().with(system_1, "system_1", &[]);
+        assert_eq!(systems(), systems());
+        assert_eq!(systems(), Systems::new().with(system_2, "system_1", &[]));
+        assert_ne!(systems(), systems().with(system_2, "system_2", &[]));
+        assert_ne!(
+            systems(),
+            Systems::new().with(system_1.in_set("set"), "system_1", &[])
+        );
+        assert!(systems() < systems().with(system_2, "system_2", &[]));
+    }
+
+    #[test]
+    #[should_panic(expected = "conflicts with a previous parameter")]
+    fn conflicting_params() {
+        fn read_and_write(_: Res<u32>, _: ResMut<u32>) {}
+        Systems::new().with(read_and_write, "read_and_write", &[]);
+    }
+
+    #[test]
+    fn compatible_params() {
+        fn read_twice(_: Res<u32>, _: Option<Res<u32>>, _: ResMut<u64>) {}
+        Systems::new().with(read_twice, "read_twice", &[]);
     }
 
     fn system_1(_: &World) {}
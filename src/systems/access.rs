@@ -0,0 +1,172 @@
+use std::{
+    any::{Any, TypeId},
+    collections::HashSet,
+};
+
+/// The components and resources a system reads and writes.
+/// The [`Dispatcher`](super::dispatcher::Dispatcher) never runs systems with conflicting access in the same stage.
+///
+/// Systems taking [`SystemParam`](super::param::SystemParam)s derive their access from their parameters.
+/// A [`Query`](crate::entities::query::Query) parameter only declares the components of its [`QueryData`](crate::entities::query::QueryData).
+/// Components added at runtime, e.g. with [`Query::with`](crate::entities::query::Query::with),
+/// [`Query::changed`](crate::entities::query::Query::changed) or [`Query::read_id`](crate::entities::query::Query::read_id),
+/// are locked while the query runs without being declared, so a system writing them may share the stage and the query waits for its lock.
+/// Order such systems with dependencies or [`IntoSystem::after`](super::IntoSystem::after) to keep them apart.
+/// Systems taking a `&World` don't declare their access, unless it is declared with [`with_access`](super::with_access).
+/// They run in parallel with each other, but not with systems that declare access to anything.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SystemAccess {
+    component_reads: HashSet<TypeId>,
+    component_writes: HashSet<TypeId>,
+    resource_reads: HashSet<TypeId>,
+    resource_writes: HashSet<TypeId>,
+    /// Writes components that are only known at runtime.
+    all_components: bool,
+    world: bool,
+    /// The access of a system taking a `&World` isn't known.
+    undeclared: bool,
+}
+
+impl SystemAccess {
+    /// Access to nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Access to the whole world, conflicting with every other system.
+    pub fn world() -> Self {
+        Self {
+            world: true,
+            ..Self::default()
+        }
+    }
+
+    /// The unknown access of a system taking a `&World`.
+    pub(crate) fn undeclared() -> Self {
+        Self {
+            undeclared: true,
+            ..Self::default()
+        }
+    }
+
+    /// Read component `T`.
+    pub fn read<T: Any>(mut self) -> Self {
+        self.add_read::<T>();
+        self
+    }
+
+    /// Write component `T`.
+    pub fn write<T: Any>(mut self) -> Self {
+        self.add_write::<T>();
+        self
+    }
+
+    /// Read resource `T`.
+    pub fn read_resource<T: Any>(mut self) -> Self {
+        self.add_resource_read::<T>();
+        self
+    }
+
+    /// Write resource `T`.
+    pub fn write_resource<T: Any>(mut self) -> Self {
+        self.add_resource_write::<T>();
+        self
+    }
+
+    pub(crate) fn add_read<T: Any>(&mut self) {
+        self.component_reads.insert(TypeId::of::<T>());
+    }
+
+    pub(crate) fn add_write<T: Any>(&mut self) {
+        self.component_writes.insert(TypeId::of::<T>());
+    }
+
+    pub(crate) fn add_resource_read<T: Any>(&mut self) {
+        self.resource_reads.insert(TypeId::of::<T>());
+    }
+
+    pub(crate) fn add_resource_write<T: Any>(&mut self) {
+        self.resource_writes.insert(TypeId::of::<T>());
+    }
+
+    /// Write all components, for data that is only known at runtime.
+    pub(crate) fn add_all_components(&mut self) {
+        self.all_components = true;
+    }
+
+    /// Add everything another access accesses.
+    pub(crate) fn extend(&mut self, other: &SystemAccess) {
+        self.component_reads.extend(&other.component_reads);
+        self.component_writes.extend(&other.component_writes);
+        self.resource_reads.extend(&other.resource_reads);
+        self.resource_writes.extend(&other.resource_writes);
+        self.all_components |= other.all_components;
+        self.world |= other.world;
+        self.undeclared |= other.undeclared;
+    }
+
+    fn accesses_components(&self) -> bool {
+        self.all_components || !self.component_reads.is_empty() || !self.component_writes.is_empty()
+    }
+
+    fn accesses_anything(&self) -> bool {
+        self.world
+            || self.accesses_components()
+            || !self.resource_reads.is_empty()
+            || !self.resource_writes.is_empty()
+    }
+
+    /// Check if two systems with these accesses can't run at the same time.
+    pub fn conflicts_with(&self, other: &SystemAccess) -> bool {
+        self.world
+            || other.world
+            || (self.undeclared && other.accesses_anything())
+            || (other.undeclared && self.accesses_anything())
+            || (self.all_components && other.accesses_components())
+            || (other.all_components && self.accesses_components())
+            || sets_conflict(
+                (&self.component_reads, &self.component_writes),
+                (&other.component_reads, &other.component_writes),
+            )
+            || sets_conflict(
+                (&self.resource_reads, &self.resource_writes),
+                (&other.resource_reads, &other.resource_writes),
+            )
+    }
+}
+
+/// Check if one of two `(reads, writes)` pairs writes something the other one accesses.
+fn sets_conflict(
+    (reads, writes): (&HashSet<TypeId>, &HashSet<TypeId>),
+    (other_reads, other_writes): (&HashSet<TypeId>, &HashSet<TypeId>),
+) -> bool {
+    !writes.is_disjoint(other_reads)
+        || !writes.is_disjoint(other_writes)
+        || !reads.is_disjoint(other_writes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::SystemAccess;
+
+    #[test]
+    fn conflicts() {
+        let read = SystemAccess::new().read::<u32>().read_resource::<u64>();
+        assert!(!read.conflicts_with(&read));
+        assert!(read.conflicts_with(&SystemAccess::new().write::<u32>()));
+        assert!(SystemAccess::new()
+            .write_resource::<u64>()
+            .conflicts_with(&read));
+        assert!(!read.conflicts_with(&SystemAccess::new().write_resource::<u32>()));
+        assert!(!read.conflicts_with(&SystemAccess::new().write::<u64>()));
+        assert!(read.conflicts_with(&SystemAccess::world()));
+        assert!(SystemAccess::world().conflicts_with(&SystemAccess::new()));
+
+        let undeclared = SystemAccess::undeclared();
+        assert!(!undeclared.conflicts_with(&undeclared));
+        assert!(!undeclared.conflicts_with(&SystemAccess::new()));
+        assert!(undeclared.conflicts_with(&read));
+        assert!(read.conflicts_with(&undeclared));
+        assert!(undeclared.conflicts_with(&SystemAccess::world()));
+    }
+}
@@ -0,0 +1,104 @@
+use std::{
+    any::Any,
+    time::{Duration, Instant},
+};
+
+use crate::World;
+
+/// Run if the resource `T` exists.
+/// ```
+/// use magma_ecs::{
+///     systems::{condition::resource_exists, IntoSystem, Systems},
+///     World,
+/// };
+///
+/// struct Editor;
+///
+/// fn editor_ui(_: &World) {}
+///
+/// let dispatcher = Systems::new()
+///     .with(editor_ui.run_if(resource_exists::<Editor>()), "editor_ui", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// ```
+pub fn resource_exists<T: Any + Send + Sync>() -> impl FnMut(&World) -> bool + Send + Clone {
+    |world: &World| world.resource_ref(|_: &T| {}).is_ok()
+}
+
+/// Run if the resource `S` exists and equals `state`.
+/// ```
+/// use magma_ecs::{
+///     systems::{condition::in_state, IntoSystem, Systems},
+///     World,
+/// };
+///
+/// #[derive(PartialEq)]
+/// enum GameState {
+///     Menu,
+///     Playing,
+/// }
+///
+/// fn movement(_: &World) {}
+///
+/// let world = World::new();
+/// world.add_resource(GameState::Menu).unwrap();
+/// let dispatcher = Systems::new()
+///     .with(movement.run_if(in_state(GameState::Playing)), "movement", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// assert!(dispatcher.dispatch(&world).is_empty());
+/// ```
+pub fn in_state<S: PartialEq + Any + Send + Sync>(state: S) -> impl FnMut(&World) -> bool + Send {
+    move |world: &World| {
+        let mut equal = false;
+        let _ = world.resource_ref(|current: &S| equal = *current == state);
+        equal
+    }
+}
+
+/// Run once every time `duration` has passed since the condition was created or last returned `true`.
+pub fn on_timer(duration: Duration) -> impl FnMut(&World) -> bool + Send + Clone {
+    let mut last = Instant::now();
+    move |_: &World| {
+        let elapsed = last.elapsed() >= duration;
+        if elapsed {
+            last = Instant::now();
+        }
+        elapsed
+    }
+}
+
+/// Run if the condition returns `false`.
+pub fn not(mut condition: impl FnMut(&World) -> bool + Send) -> impl FnMut(&World) -> bool + Send {
+    move |world: &World| !condition(world)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::World;
+
+    use super::{in_state, not, on_timer, resource_exists};
+
+    #[test]
+    fn conditions() {
+        let world = World::new();
+        let mut exists = resource_exists::<u32>();
+        let mut playing = in_state(1_u32);
+        assert!(!exists(&world));
+        assert!(!playing(&world));
+        assert!(not(in_state(1_u32))(&world));
+
+        world.add_resource(2_u32).unwrap();
+        assert!(exists(&world));
+        assert!(!playing(&world));
+        world.resource_mut(|state: &mut u32| *state = 1).unwrap();
+        assert!(playing(&world));
+
+        let mut timer = on_timer(Duration::from_secs(60));
+        assert!(!timer(&world));
+        let mut timer = on_timer(Duration::ZERO);
+        assert!(timer(&world));
+    }
+}
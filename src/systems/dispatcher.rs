@@ -1,52 +1,243 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use parking_lot::Mutex;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
-use crate::World;
+use crate::{
+    error::{EntityError, ScheduleError},
+    World,
+};
+
+use super::{BoxedCondition, BoxedSystem, System, SystemSet, Systems};
+
+/// A system in a [`Dispatcher`] together with the world tick of its last run.
+struct DispatchedSystem {
+    // Locked while the system runs, so it can mutate its state while the dispatcher is shared between threads.
+    run: Mutex<BoxedSystem>,
+    last_run: AtomicU32,
+    /// The indices of the conditions of the system and its sets in the [`Dispatcher`].
+    conditions: Vec<usize>,
+}
+
+impl DispatchedSystem {
+    fn new(run: BoxedSystem, conditions: Vec<usize>) -> Self {
+        Self {
+            run: Mutex::new(run),
+            last_run: AtomicU32::new(0),
+            conditions,
+        }
+    }
+
+    /// Run the system with a new world tick, so queries can detect changes since its last run.
+    fn run(&self, world: &World) {
+        let ticks = world
+            .entities
+            .system_ticks(self.last_run.load(Ordering::Acquire));
+        let mut run = self.run.lock();
+        ticks.run(|| run(world));
+        self.last_run.store(ticks.this_run, Ordering::Release);
+    }
+}
 
-use super::{System, Systems};
+impl std::fmt::Debug for DispatchedSystem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DispatchedSystem")
+            .field("last_run", &self.last_run)
+            .field("conditions", &self.conditions)
+            .finish_non_exhaustive()
+    }
+}
 
 /// Used to dispatch [`Systems`] on a [`World`] in parallel
-#[derive(Default, Debug, Clone)]
-pub struct Dispatcher(Vec<Vec<fn(&World)>>);
+///
+/// Unlike in earlier versions, a dispatcher is not `Clone`: systems and run conditions can be closures
+/// keeping state between runs, e.g. in a [`Local`](super::param::Local), which can't be cloned.
+/// To get another dispatcher, describe the same [`Systems`] again, e.g. in a function, and call [`Systems::build_dispatcher`] on them.
+#[derive(Default)]
+pub struct Dispatcher {
+    stages: Vec<Vec<DispatchedSystem>>,
+    // Locked while evaluated, like the systems.
+    conditions: Vec<Mutex<BoxedCondition>>,
+}
+
+impl std::fmt::Debug for Dispatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dispatcher")
+            .field("stages", &self.stages)
+            .field("conditions", &self.conditions.len())
+            .finish()
+    }
+}
 
 impl Dispatcher {
-    /// A system can't have shared dependencies with systems it depends on (the shared dependency is already guaranteed to have executed because of the other dependency).
-    /// This function will lock when this happends. Will be improved in the future!
-    pub(crate) fn from_systems(mut systems: Systems) -> Self {
-        let mut in_dispatcher: Vec<System> = vec![];
-        let mut dispatcher = Self::default();
-        let mut stage: Vec<System>;
+    /// Build the stages of the dispatcher. Every system runs in a stage after the systems it depends on or is ordered after,
+    /// including the systems of [`SystemSet`](super::SystemSet)s it is ordered after,
+    /// and after the systems placed before it that it conflicts with, as declared by their [`SystemAccess`](super::access::SystemAccess).
+    /// Otherwise it runs in the earliest stage, in parallel with the other systems of the stage.
+    pub(crate) fn from_systems(systems: Systems) -> Result<Self, ScheduleError> {
+        let Systems { systems, sets } = systems;
+        let predecessors = Self::predecessors(&systems, &sets)?;
+        let mut stages: Vec<Option<usize>> = vec![None; systems.len()];
+        // the index and stage of every placed system in the order they were placed
+        let mut placed: Vec<(usize, usize)> = vec![];
 
-        while !systems.0.is_empty() {
-            stage = systems
-                .0
-                .iter()
-                .filter(|system| {
-                    system
-                        .deps
+        while placed.len() < systems.len() {
+            // place the first system whose predecessors are placed, so systems are placed in the order they were added
+            let Some(index) = (0..systems.len()).find(|&index| {
+                stages[index].is_none()
+                    && predecessors[index]
                         .iter()
-                        .filter(|dep| {
-                            !in_dispatcher
-                                .iter()
-                                .filter(|system| system.name == **dep)
-                                .collect::<Vec<&System>>()
-                                .is_empty()
-                        })
-                        .collect::<Vec<&&str>>()
-                        .len()
-                        == system.deps.len()
+                        .all(|&predecessor| stages[predecessor].is_some())
+            }) else {
+                return Err(ScheduleError::DependencyCycle(Self::find_cycle(
+                    &systems,
+                    &predecessors,
+                    &stages,
+                )));
+            };
+
+            let stage = placed
+                .iter()
+                .filter(|(other, _)| {
+                    predecessors[index].contains(other)
+                        || systems[*other]
+                            .access
+                            .conflicts_with(&systems[index].access)
                 })
-                .cloned()
-                .collect::<Vec<System>>();
-            systems.0.retain(|system| !stage.contains(system));
-            dispatcher
-                .0
-                .push(stage.iter().map(|system| system.run).collect());
-            in_dispatcher.append(&mut stage);
+                .map(|(_, stage)| stage + 1)
+                .max()
+                .unwrap_or(0);
+            stages[index] = Some(stage);
+            placed.push((index, stage));
         }
-        dispatcher
+
+        let mut dispatcher = Self::default();
+        // the indices of the conditions of every set
+        let mut set_conditions: HashMap<&str, Vec<usize>> = HashMap::new();
+        for set in sets {
+            for condition in set.conditions {
+                set_conditions
+                    .entry(set.name)
+                    .or_default()
+                    .push(dispatcher.conditions.len());
+                dispatcher.conditions.push(Mutex::new(condition));
+            }
+        }
+
+        let mut systems: Vec<_> = systems.into_iter().map(Some).collect();
+        for (index, stage) in placed {
+            let system = systems[index].take().unwrap();
+            let mut conditions = vec![];
+            for condition in system.conditions {
+                conditions.push(dispatcher.conditions.len());
+                dispatcher.conditions.push(Mutex::new(condition));
+            }
+            for set in &system.order.sets {
+                conditions.extend(set_conditions.get(set).into_iter().flatten());
+            }
+
+            if stage == dispatcher.stages.len() {
+                dispatcher.stages.push(vec![]);
+            }
+            dispatcher.stages[stage].push(DispatchedSystem::new(system.run, conditions));
+        }
+        Ok(dispatcher)
+    }
+
+    /// Resolve the dependencies, `before` and `after` orderings of the systems and the orderings of the sets
+    /// into the indices of the systems every system has to run after.
+    /// Checks that the system names are unique and all ordered names exist.
+    fn predecessors(
+        systems: &[System],
+        sets: &[SystemSet],
+    ) -> Result<Vec<Vec<usize>>, ScheduleError> {
+        let mut names = HashMap::new();
+        for (index, system) in systems.iter().enumerate() {
+            if names.insert(system.name, index).is_some() {
+                return Err(ScheduleError::DuplicateSystem(system.name));
+            }
+        }
+        let mut members: HashMap<&str, Vec<usize>> =
+            sets.iter().map(|set| (set.name, vec![])).collect();
+        for (index, system) in systems.iter().enumerate() {
+            for set in &system.order.sets {
+                members.entry(set).or_default().push(index);
+            }
+        }
+
+        // a name refers to the system with that name and all systems in the set with that name
+        let resolve =
+            |owner: &'static str, name: &'static str| match (names.get(name), members.get(name)) {
+                (None, None) => Err(ScheduleError::UnknownDependency(owner, name)),
+                (system, set) => Ok(system
+                    .into_iter()
+                    .chain(set.into_iter().flatten())
+                    .copied()
+                    .collect::<Vec<_>>()),
+            };
+
+        let mut predecessors = vec![vec![]; systems.len()];
+        for (index, system) in systems.iter().enumerate() {
+            for &after in &system.order.after {
+                let previous = resolve(system.name, after)?;
+                predecessors[index].extend(previous);
+            }
+            for &before in &system.order.before {
+                for next in resolve(system.name, before)? {
+                    predecessors[next].push(index);
+                }
+            }
+        }
+        for set in sets {
+            let set_members = &members[set.name];
+            for &after in &set.after {
+                let previous = resolve(set.name, after)?;
+                for &member in set_members {
+                    predecessors[member].extend(&previous);
+                }
+            }
+            for &before in &set.before {
+                for next in resolve(set.name, before)? {
+                    predecessors[next].extend(set_members);
+                }
+            }
+        }
+        Ok(predecessors)
+    }
+
+    /// Find a cycle among systems that can't be placed.
+    /// Every one of them has to run after another one of them, so following the predecessors has to run into a cycle.
+    fn find_cycle(
+        systems: &[System],
+        predecessors: &[Vec<usize>],
+        stages: &[Option<usize>],
+    ) -> Vec<&'static str> {
+        let mut path = vec![];
+        let mut index = stages.iter().position(Option::is_none).unwrap();
+        while !path.contains(&index) {
+            path.push(index);
+            index = *predecessors[index]
+                .iter()
+                .find(|&&predecessor| stages[predecessor].is_none())
+                .expect("unplaced systems run after unplaced systems");
+        }
+        let start = path.iter().position(|&other| other == index).unwrap();
+        path[start..]
+            .iter()
+            .map(|&index| systems[index].name)
+            .collect()
     }
 
     /// Dispatch on a [`World`].
+    /// Every system run advances the world tick, which is used to detect changes since the system's last run.
+    /// Every dispatch is a frame: component removals recorded before the previous dispatch are cleared and the [`Events`](crate::events::Events) are updated.
+    /// [`Commands`](crate::commands::Commands) recorded by the systems of a stage are applied before the next stage runs.
+    /// Returns the errors of the commands that failed.
+    /// Before a stage runs, the run conditions of its systems are evaluated and systems with a condition returning `false` are skipped.
+    /// Every condition is evaluated at most once per dispatch, so a set's condition applies to all of its systems alike.
     /// ```
     /// use magma_ecs::{systems::Systems, World};
     ///
@@ -54,32 +245,75 @@ impl Dispatcher {
     ///
     /// let dispatcher = Systems::new()
     ///     .with(example_system, "example_system", &[])
-    ///     .build_dispatcher();
+    ///     .build_dispatcher()
+    ///     .unwrap();
     ///
-    /// dispatcher.dispatch(&world);
+    /// assert!(dispatcher.dispatch(&world).is_empty());
     ///
     ///
     /// fn example_system(_: &World) {
     ///     // ...
     /// }
-    pub fn dispatch(&self, world: &World) {
-        self.0.iter().for_each(|systems| {
+    #[must_use = "failed commands are only reported by the returned errors"]
+    pub fn dispatch(&self, world: &World) -> Vec<EntityError> {
+        Self::start_frame(world);
+        self.run(world)
+    }
+
+    /// Age the events and the removal logs, which happens once per frame.
+    pub(crate) fn start_frame(world: &World) {
+        world.clear_removed();
+        world.events.update(world);
+    }
+
+    /// Run the stages without starting a new frame.
+    pub(crate) fn run(&self, world: &World) -> Vec<EntityError> {
+        // the results of the conditions evaluated so far
+        let mut results = vec![None; self.conditions.len()];
+        let mut errors = vec![];
+        self.stages.iter().for_each(|systems| {
+            let systems: Vec<_> = systems
+                .iter()
+                .filter(|system| self.should_run(system, &mut results, world))
+                .collect();
             systems.par_iter().for_each(|system| {
-                (system)(world);
-            })
+                system.run(world);
+            });
+            errors.extend(world.apply_commands());
         });
+        errors
+    }
+
+    /// Evaluate the conditions of a system that weren't evaluated in this dispatch yet.
+    /// All of them are evaluated, even if one already returned `false`, so conditions keeping state behave the same for every system.
+    fn should_run(
+        &self,
+        system: &DispatchedSystem,
+        results: &mut [Option<bool>],
+        world: &World,
+    ) -> bool {
+        let mut run = true;
+        for &condition in &system.conditions {
+            run &= *results[condition]
+                .get_or_insert_with(|| (self.conditions[condition].lock())(world));
+        }
+        run
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{systems::Systems, World};
+    use crate::{
+        entities::query::Query,
+        systems::{access::SystemAccess, with_access, IntoSystem, SystemSet, Systems},
+        World,
+    };
 
     use super::Dispatcher;
 
     #[test]
     fn create_dispatcher() {
-        let mut world = World::new();
+        let world = World::new();
         world.register_component::<u32>();
 
         let systems = Systems::new()
@@ -87,9 +321,9 @@ mod tests {
             .with(system_2, "system_2", &["system_1"])
             .with(system_3, "system_3", &["system_1"])
             .with(system_4, "system_4", &["system_2", "system_3"]);
-        let dispatcher = Dispatcher::from_systems(systems);
-        (dispatcher.0[0][0])(&world);
-        (dispatcher.0[1][0])(&world);
+        let dispatcher = Dispatcher::from_systems(systems).unwrap();
+        dispatcher.stages[0][0].run(&world);
+        dispatcher.stages[1][0].run(&world);
         world
             .query()
             .with_component::<u32>()
@@ -106,7 +340,7 @@ mod tests {
 
     #[test]
     fn dispatcher_dispatch() {
-        let mut world = World::new();
+        let world = World::new();
         world.register_component::<u32>();
 
         let systems = Systems::new()
@@ -114,9 +348,9 @@ mod tests {
             .with(system_2, "system_2", &["system_1"])
             .with(system_3, "system_3", &["system_1"])
             .with(system_4, "system_4", &["system_2", "system_3"]);
-        let dispatcher = Dispatcher::from_systems(systems);
+        let dispatcher = Dispatcher::from_systems(systems).unwrap();
 
-        dispatcher.dispatch(&world);
+        assert!(dispatcher.dispatch(&world).is_empty());
 
         world
             .query()
@@ -132,6 +366,57 @@ mod tests {
             });
     }
 
+    #[test]
+    fn schedule_by_access() {
+        let systems = Systems::new()
+            .with(read_u32, "read_u32", &[])
+            .with(write_u32, "write_u32", &[])
+            .with(read_u32, "read_u32_again", &[])
+            .with(write_f32, "write_f32", &[])
+            .with(
+                with_access(system_1, SystemAccess::new().write::<u32>()),
+                "declared",
+                &[],
+            )
+            .with(write_f32, "after_read", &["read_u32_again"]);
+        let dispatcher = Dispatcher::from_systems(systems).unwrap();
+        let stages: Vec<_> = dispatcher.stages.iter().map(Vec::len).collect();
+        // read_u32 and write_f32 | write_u32 | read_u32_again | declared, after_read
+        assert_eq!(stages, vec![2, 1, 1, 2]);
+    }
+
+    #[test]
+    fn schedule_world_systems() {
+        let systems = Systems::new()
+            .with(system_1, "system_1", &[])
+            .with(system_2, "system_2", &[])
+            .with(read_u32, "read_u32", &[])
+            .with(system_3, "system_3", &[]);
+        let dispatcher = Dispatcher::from_systems(systems).unwrap();
+        let stages: Vec<_> = dispatcher.stages.iter().map(Vec::len).collect();
+        // system_1, system_2 | read_u32 | system_3
+        assert_eq!(stages, vec![2, 1, 1]);
+    }
+
+    #[test]
+    fn schedule_by_order() {
+        let systems = Systems::new()
+            .with_set(SystemSet::new("first").before("second"))
+            .with(read_u32.in_set("second"), "second_a", &[])
+            .with(read_u32.after("last").in_set("second"), "second_b", &[])
+            .with(read_u32.in_set("first"), "first", &[])
+            .with(read_u32.before("second_b"), "last", &[])
+            .with(read_u32.after("first").before("last"), "between", &[]);
+        let dispatcher = Dispatcher::from_systems(systems).unwrap();
+        let stages: Vec<_> = dispatcher.stages.iter().map(Vec::len).collect();
+        // first | second_a, between | last | second_b
+        assert_eq!(stages, vec![1, 2, 1, 1]);
+    }
+
+    fn read_u32(_: Query<&u32>) {}
+    fn write_u32(_: Query<&mut u32>) {}
+    fn write_f32(_: Query<(&mut f32, Option<&u64>)>) {}
+
     fn system_1(world: &World) {
         world.create_entity((1_u32,)).unwrap();
     }
@@ -0,0 +1,259 @@
+use std::{
+    any::{type_name, Any},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use parking_lot::{ArcRwLockReadGuard, ArcRwLockWriteGuard, RawRwLock};
+
+use super::access::SystemAccess;
+use crate::{
+    commands::Commands,
+    entities::query::{Query, QueryData},
+    World,
+};
+
+/// Data a function system fetches from the [`World`] before it runs.
+/// Implemented for [`Query`], [`Res`], [`ResMut`], [`Commands`], [`Local`], [`Option`]s of resources and tuples of parameters.
+pub trait SystemParam {
+    /// State kept by the system between runs.
+    type State: Send + 'static;
+    /// The parameter with the lifetimes of the [`World`] and the state it was fetched from.
+    type Item<'w, 's>;
+
+    /// Create the state when the system is created.
+    fn init_state() -> Self::State;
+
+    /// Fetch the parameter. Panics if it doesn't exist, for example a missing resource.
+    fn fetch<'w, 's>(state: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's>;
+
+    /// Declare the components and resources accessed by the parameter, so the system can be scheduled.
+    fn access(access: &mut SystemAccess);
+}
+
+/// The item of a [`SystemParam`] fetched from a [`World`] with lifetime `'w` and state with lifetime `'s`.
+pub type SystemParamItem<'w, 's, P> = <P as SystemParam>::Item<'w, 's>;
+
+/// Read access to a resource, locked for as long as this exists.
+pub struct Res<T: Any + Send + Sync> {
+    resource: ArcRwLockReadGuard<RawRwLock, dyn Any + Send + Sync>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Any + Send + Sync> Deref for Res<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.resource.downcast_ref().unwrap()
+    }
+}
+
+/// Write access to a resource, locked for as long as this exists.
+pub struct ResMut<T: Any + Send + Sync> {
+    resource: ArcRwLockWriteGuard<RawRwLock, dyn Any + Send + Sync>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: Any + Send + Sync> Deref for ResMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.resource.downcast_ref().unwrap()
+    }
+}
+
+impl<T: Any + Send + Sync> DerefMut for ResMut<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.resource.downcast_mut().unwrap()
+    }
+}
+
+impl<T: Any + Send + Sync> SystemParam for Option<Res<T>> {
+    type State = ();
+    type Item<'w, 's> = Option<Res<T>>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(_: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        Some(Res {
+            resource: world.resources.read_arc::<T>().ok()?,
+            _marker: PhantomData,
+        })
+    }
+
+    fn access(access: &mut SystemAccess) {
+        access.add_resource_read::<T>();
+    }
+}
+
+impl<T: Any + Send + Sync> SystemParam for Res<T> {
+    type State = ();
+    type Item<'w, 's> = Res<T>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(state: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        <Option<Res<T>>>::fetch(state, world)
+            .unwrap_or_else(|| panic!("resource {} does not exist", type_name::<T>()))
+    }
+
+    fn access(access: &mut SystemAccess) {
+        access.add_resource_read::<T>();
+    }
+}
+
+impl<T: Any + Send + Sync> SystemParam for Option<ResMut<T>> {
+    type State = ();
+    type Item<'w, 's> = Option<ResMut<T>>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(_: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        Some(ResMut {
+            resource: world.resources.write_arc::<T>().ok()?,
+            _marker: PhantomData,
+        })
+    }
+
+    fn access(access: &mut SystemAccess) {
+        access.add_resource_write::<T>();
+    }
+}
+
+impl<T: Any + Send + Sync> SystemParam for ResMut<T> {
+    type State = ();
+    type Item<'w, 's> = ResMut<T>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(state: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        <Option<ResMut<T>>>::fetch(state, world)
+            .unwrap_or_else(|| panic!("resource {} does not exist", type_name::<T>()))
+    }
+
+    fn access(access: &mut SystemAccess) {
+        access.add_resource_write::<T>();
+    }
+}
+
+impl<D: QueryData> SystemParam for Query<'_, D> {
+    type State = ();
+    type Item<'w, 's> = Query<'w, D>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(_: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        world.query()
+    }
+
+    /// Only the components of the [`QueryData`] are declared, not the ones added as filters when the system runs.
+    fn access(access: &mut SystemAccess) {
+        D::system_access(access);
+    }
+}
+
+impl SystemParam for Commands<'_> {
+    type State = ();
+    type Item<'w, 's> = Commands<'w>;
+
+    fn init_state() -> Self::State {}
+
+    fn fetch<'w, 's>(_: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+        world.commands()
+    }
+
+    /// Commands are applied after the stage, so they don't access anything while the system runs.
+    fn access(_: &mut SystemAccess) {}
+}
+
+/// State that is local to a system and kept between its runs. It starts with the default value.
+/// ```
+/// use magma_ecs::{
+///     systems::{
+///         param::{Local, ResMut},
+///         Systems,
+///     },
+///     World,
+/// };
+///
+/// fn count_runs(mut runs: Local<u32>, mut total: ResMut<u32>) {
+///     *runs += 1;
+///     *total = *runs;
+/// }
+///
+/// let world = World::new();
+/// world.add_resource(0_u32).unwrap();
+/// let dispatcher = Systems::new()
+///     .with(count_runs, "count_runs", &[])
+///     .build_dispatcher()
+///     .unwrap();
+/// assert!(dispatcher.dispatch(&world).is_empty());
+/// assert!(dispatcher.dispatch(&world).is_empty());
+/// world.resource_ref(|total: &u32| assert_eq!(*total, 2)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Local<'s, T: Default + Send + 'static>(&'s mut T);
+
+impl<T: Default + Send + 'static> Deref for Local<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<T: Default + Send + 'static> DerefMut for Local<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0
+    }
+}
+
+impl<T: Default + Send + 'static> SystemParam for Local<'_, T> {
+    type State = T;
+    type Item<'w, 's> = Local<'s, T>;
+
+    fn init_state() -> Self::State {
+        T::default()
+    }
+
+    fn fetch<'w, 's>(state: &'s mut Self::State, _: &'w World) -> Self::Item<'w, 's> {
+        Local(state)
+    }
+
+    /// The state belongs to the system, so it doesn't conflict with other systems.
+    fn access(_: &mut SystemAccess) {}
+}
+
+macro_rules! impl_system_param {
+    ($($name:ident),*) => {
+        impl<$($name: SystemParam),*> SystemParam for ($($name,)*) {
+            type State = ($($name::State,)*);
+            type Item<'w, 's> = ($($name::Item<'w, 's>,)*);
+
+            fn init_state() -> Self::State {
+                ($($name::init_state(),)*)
+            }
+
+            #[allow(non_snake_case)]
+            fn fetch<'w, 's>(state: &'s mut Self::State, world: &'w World) -> Self::Item<'w, 's> {
+                let ($($name,)*) = state;
+                ($($name::fetch($name, world),)*)
+            }
+
+            fn access(access: &mut SystemAccess) {
+                $($name::access(access);)*
+            }
+        }
+    };
+}
+
+impl_system_param!(P0);
+impl_system_param!(P0, P1);
+impl_system_param!(P0, P1, P2);
+impl_system_param!(P0, P1, P2, P3);
+impl_system_param!(P0, P1, P2, P3, P4);
+impl_system_param!(P0, P1, P2, P3, P4, P5);
+impl_system_param!(P0, P1, P2, P3, P4, P5, P6);
+impl_system_param!(P0, P1, P2, P3, P4, P5, P6, P7);
+impl_system_param!(P0, P1, P2, P3, P4, P5, P6, P7, P8);
+impl_system_param!(P0, P1, P2, P3, P4, P5, P6, P7, P8, P9);
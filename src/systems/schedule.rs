@@ -0,0 +1,274 @@
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{error::EntityError, World};
+
+use super::dispatcher::Dispatcher;
+
+/// The schedules run by [`Schedules::run`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScheduleLabel {
+    /// Runs once, before the first frame.
+    Startup,
+    /// Runs every frame, first.
+    PreUpdate,
+    /// Runs zero or more times every frame after [`ScheduleLabel::PreUpdate`], once for every [`Time::fixed_delta`] that passed.
+    FixedUpdate,
+    /// Runs every frame, after [`ScheduleLabel::FixedUpdate`].
+    Update,
+    /// Runs every frame, last.
+    PostUpdate,
+}
+
+/// The resource holding the frame time, updated by [`Schedules::run`] before every frame.
+/// Systems in [`ScheduleLabel::FixedUpdate`] should use [`Time::fixed_delta`] instead of [`Time::delta`].
+#[derive(Debug, Clone)]
+pub struct Time {
+    delta: Duration,
+    elapsed: Duration,
+    fixed_delta: Duration,
+    /// The longest time a single frame advances by.
+    max_delta: Duration,
+    /// The time that passed and isn't consumed by fixed steps yet.
+    accumulator: Duration,
+    last_update: Option<Instant>,
+}
+
+impl Default for Time {
+    /// The fixed timestep defaults to 64 steps per second and the maximum delta to 250 milliseconds.
+    fn default() -> Self {
+        Self::new(Duration::from_micros(15625))
+    }
+}
+
+impl Time {
+    /// Create the time with a fixed timestep and a maximum delta of 250 milliseconds. Panics if `fixed_delta` is zero.
+    pub fn new(fixed_delta: Duration) -> Self {
+        assert!(
+            !fixed_delta.is_zero(),
+            "the fixed timestep must not be zero"
+        );
+        Self {
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            fixed_delta,
+            max_delta: Duration::from_millis(250),
+            accumulator: Duration::ZERO,
+            last_update: None,
+        }
+    }
+
+    /// Set the longest time a single frame advances by on creation.
+    /// Longer frames, e.g. after a hitch or a breakpoint, are clamped, so [`ScheduleLabel::FixedUpdate`]
+    /// runs at most `max_delta / fixed_delta` times to catch up instead of stalling the following frames.
+    pub fn with_max_delta(mut self, max_delta: Duration) -> Self {
+        self.max_delta = max_delta;
+        self
+    }
+
+    /// The longest time a single frame advances by.
+    pub fn max_delta(&self) -> Duration {
+        self.max_delta
+    }
+
+    /// The time between the last two frames, clamped to [`Time::max_delta`].
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    /// The time since the first frame.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The time every run of [`ScheduleLabel::FixedUpdate`] advances.
+    pub fn fixed_delta(&self) -> Duration {
+        self.fixed_delta
+    }
+
+    /// The time that passed since the last fixed step, which can be used to interpolate between fixed steps.
+    pub fn overstep(&self) -> Duration {
+        self.accumulator
+    }
+
+    /// Advance by the time that passed since the last update. The first update advances by zero.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        let delta = self
+            .last_update
+            .map_or(Duration::ZERO, |last_update| now - last_update);
+        self.last_update = Some(now);
+        self.advance_by(delta);
+    }
+
+    /// Advance by `delta`, clamped to [`Time::max_delta`].
+    pub fn advance_by(&mut self, delta: Duration) {
+        let delta = delta.min(self.max_delta);
+        self.delta = delta;
+        self.elapsed += delta;
+        self.accumulator += delta;
+    }
+
+    /// Consume the accumulated time in fixed steps and return the number of steps.
+    pub(crate) fn fixed_steps(&mut self) -> u32 {
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_delta {
+            self.accumulator -= self.fixed_delta;
+            steps += 1;
+        }
+        steps
+    }
+}
+
+/// Runs a [`Dispatcher`] for every [`ScheduleLabel`] with one call to [`Schedules::run`].
+/// ```
+/// use magma_ecs::{
+///     systems::{
+///         param::Res,
+///         schedule::{ScheduleLabel, Schedules, Time},
+///         Systems,
+///     },
+///     World,
+/// };
+///
+/// fn setup(world: &World) {
+///     world.register_component::<f32>();
+/// }
+/// fn physics(time: Res<Time>) {
+///     let _step = time.fixed_delta();
+/// }
+///
+/// let world = World::new();
+/// let schedules = Schedules::new()
+///     .with(
+///         ScheduleLabel::Startup,
+///         Systems::new().with(setup, "setup", &[]).build_dispatcher().unwrap(),
+///     )
+///     .with(
+///         ScheduleLabel::FixedUpdate,
+///         Systems::new().with(physics, "physics", &[]).build_dispatcher().unwrap(),
+///     );
+///
+/// // the game loop
+/// for _ in 0..3 {
+///     assert!(schedules.run(&world).is_empty());
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct Schedules {
+    dispatchers: HashMap<ScheduleLabel, Dispatcher>,
+    started: AtomicBool,
+}
+
+impl Schedules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the dispatcher of a schedule on creation, replacing a dispatcher that was already added for the schedule.
+    pub fn with(mut self, label: ScheduleLabel, dispatcher: Dispatcher) -> Self {
+        self.dispatchers.insert(label, dispatcher);
+        self
+    }
+
+    /// Add the dispatcher of a schedule, replacing a dispatcher that was already added for the schedule.
+    pub fn insert(&mut self, label: ScheduleLabel, dispatcher: Dispatcher) -> &mut Self {
+        self.dispatchers.insert(label, dispatcher);
+        self
+    }
+
+    pub fn get(&self, label: ScheduleLabel) -> Option<&Dispatcher> {
+        self.dispatchers.get(&label)
+    }
+
+    /// Run a single schedule as its own frame with [`Dispatcher::dispatch`], if it was added.
+    /// Returns the errors of the commands that failed.
+    #[must_use = "failed commands are only reported by the returned errors"]
+    pub fn run_schedule(&self, label: ScheduleLabel, world: &World) -> Vec<EntityError> {
+        self.get(label)
+            .map(|dispatcher| dispatcher.dispatch(world))
+            .unwrap_or_default()
+    }
+
+    /// Run a frame. [`ScheduleLabel::Startup`] runs before the first frame.
+    /// The events and removal logs are aged once per frame, no matter how many schedules and fixed steps run,
+    /// so an event stays readable until the end of the frame after the one it was sent in.
+    /// The [`Time`] resource is added before the first frame if it doesn't exist and updated before every frame,
+    /// then the schedules run in the order [`ScheduleLabel::PreUpdate`], [`ScheduleLabel::FixedUpdate`],
+    /// [`ScheduleLabel::Update`] and [`ScheduleLabel::PostUpdate`].
+    /// Returns the errors of the commands that failed. Panics if the [`Time`] resource was removed.
+    #[must_use = "failed commands are only reported by the returned errors"]
+    pub fn run(&self, world: &World) -> Vec<EntityError> {
+        let mut errors = vec![];
+        if !self.started.swap(true, Ordering::AcqRel) {
+            if world.resource_ref(|_: &Time| {}).is_err() {
+                world.add_resource(Time::default()).unwrap();
+            }
+            errors.extend(self.run_in_frame(ScheduleLabel::Startup, world));
+        }
+
+        Dispatcher::start_frame(world);
+
+        let mut steps = 0;
+        world
+            .resource_mut(|time: &mut Time| {
+                time.update();
+                steps = time.fixed_steps();
+            })
+            .unwrap();
+
+        errors.extend(self.run_in_frame(ScheduleLabel::PreUpdate, world));
+        for _ in 0..steps {
+            errors.extend(self.run_in_frame(ScheduleLabel::FixedUpdate, world));
+        }
+        errors.extend(self.run_in_frame(ScheduleLabel::Update, world));
+        errors.extend(self.run_in_frame(ScheduleLabel::PostUpdate, world));
+        errors
+    }
+
+    /// Run a single schedule as part of the current frame, if it was added.
+    fn run_in_frame(&self, label: ScheduleLabel, world: &World) -> Vec<EntityError> {
+        self.get(label)
+            .map(|dispatcher| dispatcher.run(world))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::Time;
+
+    #[test]
+    fn fixed_steps() {
+        let mut time = Time::new(Duration::from_millis(10));
+        time.advance_by(Duration::from_millis(25));
+        assert_eq!(time.fixed_steps(), 2);
+        assert_eq!(time.overstep(), Duration::from_millis(5));
+
+        time.advance_by(Duration::from_millis(4));
+        assert_eq!(time.fixed_steps(), 0);
+        time.advance_by(Duration::from_millis(1));
+        assert_eq!(time.fixed_steps(), 1);
+        assert_eq!(time.delta(), Duration::from_millis(1));
+        assert_eq!(time.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn max_delta() {
+        let mut time =
+            Time::new(Duration::from_millis(10)).with_max_delta(Duration::from_millis(50));
+        time.advance_by(Duration::from_secs(10));
+        assert_eq!(time.delta(), Duration::from_millis(50));
+        assert_eq!(time.fixed_steps(), 5);
+        assert_eq!(time.overstep(), Duration::ZERO);
+
+        let mut time = Time::default();
+        time.advance_by(Duration::from_secs(10));
+        assert_eq!(time.fixed_steps(), 16);
+    }
+}
@@ -1,16 +1,37 @@
-use magma_ecs::World;
+use magma_ecs::{error::EntityError, World};
 
 #[test]
 fn create_entity() {
-    let mut world = World::new();
+    let world = World::new();
     world.register_component::<u64>();
 
     world.create_entity((400_u64,)).unwrap();
 }
 
+#[test]
+fn create_entity_unregistered() {
+    let world = World::new();
+    world.register_component::<u64>();
+
+    assert_eq!(
+        world.create_entity((400_u64, 1_u32)),
+        Err(EntityError::ComponentNotRegistered("u32"))
+    );
+    let mut count = 0;
+    world
+        .query()
+        .with_component::<u64>()
+        .unwrap()
+        .run(|query_entities| count = query_entities.len());
+    assert_eq!(count, 0);
+
+    let entity = world.create_entity((400_u64,)).unwrap();
+    assert_eq!(entity.index(), 0);
+}
+
 #[test]
 fn create_entity_batch() {
-    let mut world = World::new();
+    let world = World::new();
     world.register_component::<u64>();
 
     world.create_entity_batch((400_u64,), 1000).unwrap();
@@ -18,7 +39,7 @@ fn create_entity_batch() {
 
 #[test]
 fn query() {
-    let mut world = World::new();
+    let world = World::new();
     world.register_component::<u32>();
     world.create_entity((32_u32,)).unwrap();
 
@@ -48,7 +69,7 @@ fn query() {
 
 #[test]
 fn inner_lock() {
-    let mut world = World::new();
+    let world = World::new();
     world.register_component::<u32>();
     world.register_component::<f32>();
     world.create_entity((32_u32, 64.0_f32)).unwrap();
@@ -81,3 +102,208 @@ fn inner_lock() {
             }
         });
 }
+
+#[test]
+fn stale_entity() {
+    let world = World::new();
+    world.register_component::<u32>();
+    let entity = world.create_entity((32_u32,)).unwrap();
+
+    world
+        .query()
+        .with_component::<u32>()
+        .unwrap()
+        .run(|entities| entities.into_iter().for_each(|entity| entity.delete()));
+    let reused = world.create_entity((64_u32,)).unwrap();
+
+    assert_eq!(entity.index(), reused.index());
+    assert_ne!(entity, reused);
+}
+
+#[test]
+fn entity_access() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.register_component::<u64>();
+    let target = world.create_entity((32_u32,)).unwrap();
+    let follower = world.create_entity((target.index() as u64,)).unwrap();
+
+    {
+        let follower = world.entity(follower).unwrap();
+        let index = *follower.get::<u64>().unwrap();
+        assert_eq!(index as usize, target.index());
+    }
+
+    let mut target_mut = world.entity_mut(target).unwrap();
+    *target_mut.get_mut::<u32>().unwrap() += 1;
+    assert_eq!(*target_mut.get::<u32>().unwrap(), 33);
+    target_mut.insert(10_u64).unwrap();
+    assert!(target_mut.contains::<u64>());
+    assert_eq!(target_mut.take::<u64>().unwrap(), Some(10));
+    assert_eq!(target_mut.take::<u64>().unwrap(), None);
+    assert!(matches!(
+        target_mut.take::<i8>(),
+        Err(EntityError::ComponentNotRegistered(_))
+    ));
+    target_mut.despawn().unwrap();
+
+    assert!(world.entity(target).is_err());
+}
+
+#[test]
+fn removed_without_dispatcher() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.track_removed_values::<u32>().unwrap();
+
+    for frame in 0..10_u32 {
+        let entity = world.create_entity((frame,)).unwrap();
+        world.entity_mut(entity).unwrap().despawn().unwrap();
+        // removals of this and the two previous frames are kept
+        assert_eq!(
+            world.removed::<u32>().unwrap().iter().count(),
+            2.min(frame as usize) + 1
+        );
+        world.clear_removed();
+    }
+    world.clear_removed();
+    world.clear_removed();
+    assert!(world.removed::<u32>().unwrap().is_empty());
+}
+
+#[test]
+fn typed_query() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.register_component::<f32>();
+    for _ in 0..100 {
+        world.create_entity((32_u32, 2.0_f32)).unwrap();
+    }
+    world.create_entity((32_u32,)).unwrap();
+
+    world
+        .query::<(&mut u32, &f32)>()
+        .par_for_each(|(int, float)| *int *= *float as u32)
+        .unwrap();
+
+    let mut count = 0;
+    world
+        .query::<&u32>()
+        .for_each(|int| {
+            assert!(*int == 64 || *int == 32);
+            count += 1;
+        })
+        .unwrap();
+    assert_eq!(count, 101);
+}
+
+#[test]
+fn drop_removed_components() {
+    use std::sync::Arc;
+
+    let world = World::new();
+    world.register_component::<Arc<u32>>();
+    world.register_component::<String>();
+    let shared = Arc::new(10_u32);
+    let first = world
+        .create_entity((shared.clone(), String::from("first")))
+        .unwrap();
+    let second = world.create_entity((shared.clone(),)).unwrap();
+    assert_eq!(Arc::strong_count(&shared), 3);
+
+    world
+        .entity_mut(first)
+        .unwrap()
+        .remove::<Arc<u32>>()
+        .unwrap();
+    assert_eq!(Arc::strong_count(&shared), 2);
+    world.entity_mut(second).unwrap().despawn().unwrap();
+    assert_eq!(Arc::strong_count(&shared), 1);
+
+    world
+        .query()
+        .with_component::<String>()
+        .unwrap()
+        .run(|entities| {
+            assert_eq!(
+                entities[0].take_component::<String>(),
+                Some(String::from("first"))
+            );
+            assert_eq!(entities[0].take_component::<String>(), None);
+            assert_eq!(
+                entities[0].component_ref(|_: &String| {}),
+                Err(EntityError::ComponentDataDoesNotExist)
+            );
+        });
+}
+
+#[test]
+fn component_hooks() {
+    use std::sync::{Arc, Mutex};
+
+    let world = World::new();
+    world.register_component::<u32>();
+    let log = Arc::new(Mutex::new(vec![]));
+
+    let add_log = log.clone();
+    world.on_add::<u64>(move |_, _, value| add_log.lock().unwrap().push(format!("add {value}")));
+    let insert_log = log.clone();
+    world.on_insert::<u64>(move |_, _, value| {
+        insert_log.lock().unwrap().push(format!("insert {value}"))
+    });
+    let remove_log = log.clone();
+    world.on_remove::<u64>(move |world, entity, value| {
+        // the component can still be read
+        assert!(world.entity(entity).unwrap().contains::<u64>());
+        remove_log.lock().unwrap().push(format!("remove {value}"))
+    });
+
+    let entity = world.create_entity((1_u64, 10_u32)).unwrap();
+    let mut entity_mut = world.entity_mut(entity).unwrap();
+    entity_mut.insert(2_u64).unwrap();
+    entity_mut.remove::<u64>().unwrap();
+    entity_mut.remove::<u64>().unwrap();
+    entity_mut.insert(3_u64).unwrap();
+    entity_mut.despawn().unwrap();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec!["add 1", "insert 1", "insert 2", "remove 2", "add 3", "insert 3", "remove 3"]
+    );
+}
+
+#[test]
+fn deferred_hook_changes() {
+    struct Parent;
+    struct Child(magma_ecs::entities::entity::Entity);
+
+    let world = World::new();
+    world.on_add::<Parent>(|world, entity, _| {
+        world.defer(move |world| {
+            world.create_entity((Child(entity),)).unwrap();
+        });
+    });
+    world.on_remove::<Child>(|world, entity, child| {
+        let parent = child.0;
+        world.defer(move |world| {
+            world
+                .entity_mut(parent)
+                .unwrap()
+                .remove::<Parent>()
+                .unwrap();
+        });
+        assert!(world.entity(entity).unwrap().contains::<Child>());
+    });
+
+    let parent = world.create_entity((Parent,)).unwrap();
+    let mut children = vec![];
+    world
+        .query()
+        .with_component::<Child>()
+        .unwrap()
+        .run(|entities| children = entities.iter().map(|entity| entity.id).collect());
+    assert_eq!(children.len(), 1);
+
+    world.entity_mut(children[0]).unwrap().despawn().unwrap();
+    assert!(!world.entity(parent).unwrap().contains::<Parent>());
+}
@@ -1,4 +1,15 @@
-use magma_ecs::{systems::Systems, World};
+use magma_ecs::{
+    commands::Commands,
+    entities::query::Query,
+    error::EntityError,
+    systems::{
+        condition::{in_state, not, resource_exists},
+        param::{Local, Res, ResMut},
+        schedule::{ScheduleLabel, Schedules, Time},
+        IntoSystem, SystemSet, Systems,
+    },
+    World,
+};
 
 #[test]
 fn create_systems() {
@@ -32,7 +43,7 @@ fn dispatcher() {
         }
     });
 
-    let mut world = World::new();
+    let world = World::new();
     world.register_component::<u32>();
 
     let systems = Systems::new()
@@ -40,8 +51,8 @@ fn dispatcher() {
         .with(system_2, "system_2", &["system_1"])
         .with(system_3, "system_3", &["system_1"])
         .with(system_4, "system_4", &["system_2", "system_3"]);
-    let dispatcher = systems.build_dispatcher();
-    dispatcher.dispatch(&world);
+    let dispatcher = systems.build_dispatcher().unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
 
     world
         .query()
@@ -57,6 +68,425 @@ fn dispatcher() {
         });
 }
 
+#[test]
+fn change_detection() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.add_resource(0_usize).unwrap();
+    world.create_entity((1_u32,)).unwrap();
+    world.create_entity((2_u32,)).unwrap();
+
+    let dispatcher = Systems::new()
+        .with(count_changed, "count_changed", &[])
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 2))
+        .unwrap();
+
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 0))
+        .unwrap();
+
+    world
+        .query()
+        .with_component::<u32>()
+        .unwrap()
+        .run(|entities| {
+            entities[0]
+                .component_mut(|comp: &mut u32| *comp += 1)
+                .unwrap()
+        });
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 1))
+        .unwrap();
+}
+
+#[test]
+fn removed_components() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.add_resource(0_usize).unwrap();
+    let entity = world.create_entity((1_u32,)).unwrap();
+    world.create_entity((2_u32,)).unwrap();
+
+    let dispatcher = Systems::new()
+        .with(count_removed, "count_removed", &[])
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 0))
+        .unwrap();
+
+    world.entity_mut(entity).unwrap().remove::<u32>().unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 1))
+        .unwrap();
+
+    // every system sees a removal once
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|count: &usize| assert_eq!(*count, 0))
+        .unwrap();
+    assert!(!world.removed::<u32>().unwrap().is_empty());
+
+    // removals are cleared after two dispatches
+    assert!(dispatcher.dispatch(&world).is_empty());
+    assert!(world.removed::<u32>().unwrap().is_empty());
+}
+
+#[test]
+fn register_in_systems() {
+    let world = World::new();
+    let dispatcher = Systems::new()
+        .with(register_u64, "register_u64", &[])
+        .with(register_i64, "register_i64", &[])
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+
+    let mut count = 0;
+    world
+        .query::<(&u64, &i64)>()
+        .for_each(|_| count += 1)
+        .unwrap();
+    assert_eq!(count, 2);
+}
+
+#[test]
+fn commands_between_stages() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.add_resource(Vec::<usize>::new()).unwrap();
+
+    let dispatcher = Systems::new()
+        .with(spawn_with_commands, "spawn_with_commands", &[])
+        .with(record_count, "record_count", &[])
+        .with(
+            record_count_after,
+            "record_count_after",
+            &["spawn_with_commands"],
+        )
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|counts: &Vec<usize>| assert_eq!(*counts, vec![0, 3]))
+        .unwrap();
+
+    // spawning unregistered components fails when the commands are applied
+    let dispatcher = Systems::new()
+        .with(
+            |mut commands: Commands| {
+                commands.spawn((1_u32, 1_i8));
+            },
+            "spawn_unregistered",
+            &[],
+        )
+        .build_dispatcher()
+        .unwrap();
+    assert_eq!(
+        dispatcher.dispatch(&world),
+        vec![EntityError::ComponentNotRegistered("i8")]
+    );
+}
+
+#[test]
+fn events_between_systems() {
+    let world = World::new();
+    world.add_event::<u32>();
+    world.add_resource(Vec::<usize>::new()).unwrap();
+
+    let dispatcher = Systems::new()
+        .with(send_events, "send_events", &[])
+        .with(read_events, "read_events", &["send_events"])
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world.send_event(10_u32).unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|read: &Vec<usize>| assert_eq!(*read, vec![2, 3]))
+        .unwrap();
+
+    // outside of systems all kept events are read
+    assert_eq!(world.read_events::<u32>().unwrap().len(), 5);
+    assert!(dispatcher.dispatch(&world).is_empty());
+    assert_eq!(world.read_events::<u32>().unwrap().len(), 4);
+    assert!(world.send_event(1_u64).is_err());
+}
+
+#[test]
+fn function_systems() {
+    let world = World::new();
+    world.register_component::<u32>();
+    world.add_resource(2_u32).unwrap();
+    world.add_resource(0_usize).unwrap();
+    world.create_entity((1_u32,)).unwrap();
+
+    let dispatcher = Systems::new()
+        .with(scale, "scale", &[])
+        .with(sum, "sum", &["scale"])
+        .with(spawn_from_resource, "spawn_from_resource", &["sum"])
+        .with(system_1, "system_1", &["spawn_from_resource"])
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|sum: &usize| assert_eq!(*sum, 2))
+        .unwrap();
+
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|sum: &usize| assert_eq!(*sum, 4 + 4 + 2))
+        .unwrap();
+}
+
+#[test]
+fn systems_with_state() {
+    use std::sync::mpsc;
+
+    let world = World::new();
+    world.add_resource(Vec::<usize>::new()).unwrap();
+    let (sender, receiver) = mpsc::channel();
+    let tick_rate = 3;
+    let mut runs = 0;
+
+    let mut systems = Systems::new();
+    systems
+        .add(
+            move |_: &World| {
+                runs += 1;
+                if runs % tick_rate == 0 {
+                    sender.send(runs).unwrap();
+                }
+            },
+            "send_ticks",
+            &[],
+        )
+        .add(count_runs, "count_runs", &["send_ticks"]);
+    let dispatcher = systems.build_dispatcher().unwrap();
+    for _ in 0..6 {
+        assert!(dispatcher.dispatch(&world).is_empty());
+    }
+
+    assert_eq!(receiver.try_iter().collect::<Vec<_>>(), vec![3, 6]);
+    world
+        .resource_ref(|runs: &Vec<usize>| assert_eq!(*runs, vec![1, 2, 3, 4, 5, 6]))
+        .unwrap();
+}
+
+#[test]
+fn ordered_systems_and_sets() {
+    let world = World::new();
+    world.add_resource(Vec::<&'static str>::new()).unwrap();
+    // the systems write the same resource, so they run one after another
+    let log = |label: &'static str| move |mut log: ResMut<Vec<&'static str>>| log.push(label);
+
+    let dispatcher = Systems::new()
+        .with(log("draw").in_set("Render"), "draw", &[])
+        .with(log("movement").in_set("Physics"), "movement", &[])
+        .with(log("render_prep").in_set("Render"), "render_prep", &[])
+        .with(
+            log("plugin").before("render_prep").after("Physics"),
+            "plugin",
+            &[],
+        )
+        .with(log("read_input").in_set("Input"), "read_input", &[])
+        .with_set(SystemSet::new("Input").before("Physics"))
+        .with_set(SystemSet::new("Render").after("Physics"))
+        .build_dispatcher()
+        .unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+
+    world
+        .resource_ref(|log: &Vec<&'static str>| {
+            assert_eq!(
+                *log,
+                vec!["read_input", "movement", "draw", "plugin", "render_prep"]
+            )
+        })
+        .unwrap();
+}
+
+#[test]
+fn run_conditions() {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    #[derive(PartialEq)]
+    enum GameState {
+        Menu,
+        Playing,
+    }
+
+    let world = World::new();
+    world.add_resource(GameState::Menu).unwrap();
+    world.add_resource(Vec::<usize>::new()).unwrap();
+    let evaluated = Arc::new(AtomicUsize::new(0));
+    let count = evaluated.clone();
+
+    let dispatcher = Systems::new()
+        .with_set(SystemSet::new("Gameplay").run_if(move |world: &World| {
+            count.fetch_add(1, Ordering::Relaxed);
+            in_state(GameState::Playing)(world)
+        }))
+        .with(count_runs.in_set("Gameplay"), "count_runs", &[])
+        .with(
+            count_runs.in_set("Gameplay").after("count_runs"),
+            "count_runs_again",
+            &[],
+        )
+        .with(
+            count_runs.run_if(not(resource_exists::<u64>())),
+            "count_without_u64",
+            &[],
+        )
+        .build_dispatcher()
+        .unwrap();
+
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|runs: &Vec<usize>| assert_eq!(*runs, vec![1]))
+        .unwrap();
+
+    world
+        .resource_mut(|state: &mut GameState| *state = GameState::Playing)
+        .unwrap();
+    world.add_resource(0_u64).unwrap();
+    assert!(dispatcher.dispatch(&world).is_empty());
+    world
+        .resource_ref(|runs: &Vec<usize>| assert_eq!(*runs, vec![1, 1, 1]))
+        .unwrap();
+    // the set's condition is evaluated once per dispatch
+    assert_eq!(evaluated.load(Ordering::Relaxed), 2);
+}
+
+#[test]
+fn run_schedules() {
+    use std::time::Duration;
+
+    let world = World::new();
+    world.add_resource(Vec::<&'static str>::new()).unwrap();
+    world
+        .add_resource(Time::new(Duration::from_millis(1)))
+        .unwrap();
+    let log = |label: &'static str| {
+        Systems::new()
+            .with(
+                move |world: &World| {
+                    world
+                        .resource_mut(|log: &mut Vec<&'static str>| log.push(label))
+                        .unwrap()
+                },
+                label,
+                &[],
+            )
+            .build_dispatcher()
+            .unwrap()
+    };
+
+    let schedules = Schedules::new()
+        .with(ScheduleLabel::PostUpdate, log("post_update"))
+        .with(ScheduleLabel::Update, log("update"))
+        .with(ScheduleLabel::FixedUpdate, log("fixed_update"))
+        .with(ScheduleLabel::PreUpdate, log("pre_update"))
+        .with(ScheduleLabel::Startup, log("startup"));
+    assert!(schedules.run(&world).is_empty());
+    world
+        .resource_ref(|log: &Vec<&'static str>| {
+            assert_eq!(*log, vec!["startup", "pre_update", "update", "post_update"])
+        })
+        .unwrap();
+
+    std::thread::sleep(Duration::from_millis(5));
+    assert!(schedules.run(&world).is_empty());
+    let mut steps = 0;
+    world
+        .resource_ref(|time: &Time| {
+            steps = (time.elapsed().as_micros() / time.fixed_delta().as_micros()) as usize
+        })
+        .unwrap();
+    assert!(steps >= 5);
+    world
+        .resource_ref(|log: &Vec<&'static str>| {
+            assert_eq!(log[4], "pre_update");
+            assert_eq!(log[5..5 + steps], vec!["fixed_update"; steps]);
+            assert_eq!(log[5 + steps..], ["update", "post_update"]);
+        })
+        .unwrap();
+}
+
+#[test]
+fn events_across_schedules() {
+    use std::time::Duration;
+
+    // no fixed steps, then 3 fixed steps in every frame after the first
+    for (time, fixed_steps) in [
+        (Time::new(Duration::from_secs(3600)), 0),
+        (
+            Time::new(Duration::from_nanos(1)).with_max_delta(Duration::from_nanos(3)),
+            6,
+        ),
+    ] {
+        let world = World::new();
+        world.add_event::<u32>();
+        world.add_resource(time).unwrap();
+        world.add_resource(Vec::<usize>::new()).unwrap();
+        world.add_resource(0_u32).unwrap();
+
+        let empty = |label| {
+            Systems::new()
+                .with(|_: &World| {}, label, &[])
+                .build_dispatcher()
+                .unwrap()
+        };
+        let mut sent = false;
+        let send_once = move |world: &World| {
+            if !sent {
+                world.send_event(1_u32).unwrap();
+                sent = true;
+            }
+        };
+        let schedules = Schedules::new()
+            .with(ScheduleLabel::PreUpdate, empty("pre_update"))
+            .with(
+                ScheduleLabel::FixedUpdate,
+                Systems::new()
+                    .with(|mut steps: ResMut<u32>| *steps += 1, "fixed_update", &[])
+                    .build_dispatcher()
+                    .unwrap(),
+            )
+            .with(
+                ScheduleLabel::Update,
+                Systems::new()
+                    .with(read_events, "read_events", &[])
+                    .with(send_once, "send_once", &["read_events"])
+                    .build_dispatcher()
+                    .unwrap(),
+            )
+            .with(ScheduleLabel::PostUpdate, empty("post_update"));
+        for _ in 0..3 {
+            assert!(schedules.run(&world).is_empty());
+        }
+
+        world
+            .resource_ref(|steps: &u32| assert_eq!(*steps, fixed_steps))
+            .unwrap();
+        // the event sent in the first frame is read in the second frame
+        world
+            .resource_ref(|read: &Vec<usize>| assert_eq!(*read, vec![0, 1, 0]))
+            .unwrap();
+    }
+}
+
 // test systems
 fn system_1(world: &World) {
     world.create_entity((1_u32,)).unwrap();
@@ -79,3 +509,68 @@ fn system_3(world: &World) {
 fn system_4(world: &World) {
     world.create_entity((4_u32,)).unwrap();
 }
+fn count_changed(world: &World) {
+    let mut changed = 0;
+    world
+        .query::<&u32>()
+        .changed::<u32>()
+        .unwrap()
+        .for_each(|_| changed += 1)
+        .unwrap();
+    world
+        .resource_mut(|count: &mut usize| *count = changed)
+        .unwrap();
+}
+fn count_removed(world: &World) {
+    let removed = world.removed::<u32>().unwrap().entities().count();
+    world
+        .resource_mut(|count: &mut usize| *count = removed)
+        .unwrap();
+}
+fn register_u64(world: &World) {
+    world.register_component::<u64>();
+    world.register_component::<i64>();
+    world.create_entity((1_u64, 1_i64)).unwrap();
+}
+fn register_i64(world: &World) {
+    world.register_component::<i64>();
+    let entity = world.create_entity((2_i64,)).unwrap();
+    world.entity_mut(entity).unwrap().insert(2_u64).unwrap();
+}
+fn spawn_with_commands(mut commands: Commands) {
+    for i in 0..3 {
+        commands.spawn((i as u32,));
+    }
+}
+fn record_count(query: Query<&u32>, mut counts: ResMut<Vec<usize>>) {
+    let mut count = 0;
+    query.for_each(|_| count += 1).unwrap();
+    counts.push(count);
+}
+fn record_count_after(query: Query<&u32>, counts: ResMut<Vec<usize>>) {
+    record_count(query, counts);
+}
+fn send_events(world: &World) {
+    world.event_writer::<u32>().unwrap().send(1).send(2);
+}
+fn read_events(world: &World) {
+    let read = world.read_events::<u32>().unwrap().len();
+    world
+        .resource_mut(|counts: &mut Vec<usize>| counts.push(read))
+        .unwrap();
+}
+fn scale(query: Query<&mut u32>, factor: Res<u32>) {
+    query.for_each(|value| *value *= *factor).unwrap();
+}
+fn sum(query: Query<&u32>, mut sum: ResMut<usize>, missing: Option<Res<u64>>) {
+    assert!(missing.is_none());
+    *sum = 0;
+    query.for_each(|value| *sum += *value as usize).unwrap();
+}
+fn spawn_from_resource(mut commands: Commands, factor: Res<u32>) {
+    commands.spawn((*factor,));
+}
+fn count_runs(mut runs: Local<usize>, mut counts: ResMut<Vec<usize>>) {
+    *runs += 1;
+    counts.push(*runs);
+}